@@ -22,28 +22,263 @@
 
 extern crate clap;
 
-use std::io::prelude::*;
-use std::fs::File;
+use std::path::{Path, PathBuf};
 
 use clap::{Arg, App};
 
-use chippy::emulator::{Emulator, ROM_SIZE};
+use chippy::config::{AudioConfig, Config, EmulationConfig, VideoConfig};
+use chippy::emulator::{Emulator, Options, PLATFORMS};
+use chippy::rom;
+use chippy::keymap::PRESETS;
+use chippy::palette::THEMES;
 
 fn main() -> Result<(), String> {
+    let theme_help = format!(
+        "Colour theme used to draw the display: {}.",
+        THEMES.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(", ")
+    );
+
     let matches = App::new("CHIP-8 interpreter written in Rust.")
                         .version("1.0.0")
                         .author("Pedro Rodrigues <csixteen@protonmail.com>")
                         .arg(Arg::with_name("file_name")
                              .value_name("FILE")
-                             .help("CHIP-8 program source file.")
-                             .takes_value(true)
-                             .required(true))
+                             .help("CHIP-8 program: a binary, an Intel HEX file or a hexadecimal dump, or - to read it from the standard input. Without it, the launcher lists the ROMs in the games directory.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("games")
+                             .long("games")
+                             .value_name("DIR")
+                             .help("Directory listed by the launcher, which F2 opens (games by default).")
+                             .takes_value(true))
+                        .arg(Arg::with_name("config")
+                             .long("config")
+                             .value_name("FILE")
+                             .help("Configuration file to use instead of $XDG_CONFIG_HOME/chippy/config.toml.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("scale")
+                             .long("scale")
+                             .value_name("N")
+                             .help("Size of a CHIP-8 pixel in the initial window, in screen pixels.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("integer_scaling")
+                             .long("integer-scaling")
+                             .help("Only scales the display by whole numbers when the window is resized."))
+                        .arg(Arg::with_name("fullscreen")
+                             .long("fullscreen")
+                             .help("Starts in fullscreen mode."))
+                        .arg(Arg::with_name("theme")
+                             .long("theme")
+                             .value_name("NAME")
+                             .help(&theme_help)
+                             .takes_value(true))
+                        .arg(Arg::with_name("fg")
+                             .long("fg")
+                             .value_name("RRGGBB")
+                             .help("Foreground colour. Overrides the one from the theme.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("bg")
+                             .long("bg")
+                             .value_name("RRGGBB")
+                             .help("Background colour. Overrides the one from the theme.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("colors")
+                             .long("colors")
+                             .value_name("C0,C1,C2,C3")
+                             .help("All four colours used by XO-CHIP programs: background, first plane, second plane and both planes.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("deflicker")
+                             .long("deflicker")
+                             .value_name("MODE")
+                             .possible_values(&["none", "phosphor", "frame-or"])
+                             .help("Reduces flicker: phosphor fades pixels out over a few frames, frame-or only shows the display at 60 Hz with every pixel that was lit in between.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("filters")
+                             .long("filters")
+                             .value_name("LIST")
+                             .help("Comma separated list of CRT filters, applied in order: scanlines, grid, bloom, curvature. crt is short for scanlines,bloom,curvature.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("instructions_per_frame")
+                             .long("instructions-per-frame")
+                             .value_name("N")
+                             .help("Instructions executed per frame, at 60 frames per second (10 by default). Page Up and Page Down change it while running.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("platform")
+                             .long("platform")
+                             .value_name("NAME")
+                             .possible_values(&PLATFORMS)
+                             .help("Machine the program was written for, which sets the memory, where programs are loaded, the display size and the quirks.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("xo_chip")
+                             .long("xo-chip")
+                             .help("Gives programs 64 KiB of memory, like XO-CHIP, so larger ROMs can be loaded."))
+                        .arg(Arg::with_name("speed")
+                             .long("speed")
+                             .value_name("X")
+                             .help("Runs the emulator X times faster than normal, e.g. 0.5 or 2.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("fast_forward")
+                             .long("fast-forward")
+                             .value_name("X")
+                             .help("Speed of fast-forward, toggled with F8. 0, the default, runs as fast as possible.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("slow_motion")
+                             .long("slow-motion")
+                             .value_name("X")
+                             .help("Speed of slow motion, toggled with F7 (0.25 by default).")
+                             .takes_value(true))
+                        .arg(Arg::with_name("tone")
+                             .long("tone")
+                             .value_name("HZ")
+                             .help("Frequency of the buzzer (240 Hz by default).")
+                             .takes_value(true))
+                        .arg(Arg::with_name("waveform")
+                             .long("waveform")
+                             .value_name("WAVE")
+                             .possible_values(&["square", "triangle", "sine", "noise"])
+                             .help("Shape of the buzzer's wave.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("volume")
+                             .long("volume")
+                             .value_name("V")
+                             .help("Volume of the buzzer, from 0 to 1 (0.25 by default).")
+                             .takes_value(true))
+                        .arg(Arg::with_name("mute")
+                             .long("mute")
+                             .help("Starts with the buzzer muted. F9 mutes or unmutes it."))
+                        .arg(Arg::with_name("keypad_overlay")
+                             .long("keypad")
+                             .help("Shows a keypad on top of the display, which highlights the pressed buttons and can be clicked with the mouse. F10 shows or hides it."))
+                        .arg(Arg::with_name("stats_overlay")
+                             .long("stats")
+                             .help("Shows the frames and instructions per second, the instructions per frame and the program counter on top of the display. F1 shows or hides them."))
+                        .arg(Arg::with_name("keymap")
+                             .long("keymap")
+                             .value_name("NAME")
+                             .possible_values(PRESETS)
+                             .help("Built-in keymap: default is the 1234/QWER/ASDF/ZXCV layout, arrows adds the arrow keys, Space and the first game controller, pong adds two game controllers for the paddles on 1/4 and C/D.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("record")
+                             .long("record")
+                             .value_name("OUT")
+                             .help("Records the gameplay to an animated GIF. Use a .y4m file, or - for the standard output, to get a raw Y4M stream instead.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("record_audio")
+                             .long("record-audio")
+                             .value_name("OUT")
+                             .help("Records the sound to a WAV file, following emulated time.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("headless")
+                             .long("headless")
+                             .help("Runs without a window, sound or input, as fast as possible. Useful with --record and --record-audio."))
+                        .arg(Arg::with_name("frames")
+                             .long("frames")
+                             .value_name("N")
+                             .help("Number of frames to run with --headless (600 by default, 10 seconds).")
+                             .takes_value(true))
+                        .arg(Arg::with_name("log_input")
+                             .long("log-input")
+                             .value_name("FILE")
+                             .help("Logs every keypad event with the frame it reached the program at. Use - for the standard output, unless --record already writes to it.")
+                             .takes_value(true))
+                        .arg(Arg::with_name("watch")
+                             .long("watch")
+                             .help("Reloads the ROM whenever the file changes. F5 restarts the program and F6 reloads the ROM at any time."))
+                        .arg(Arg::with_name("frame_stats")
+                             .long("frame-stats")
+                             .help("Prints how long it took to draw the frames on exit."))
                         .get_matches();
 
-    let file_name = matches.value_of("file_name").unwrap();
-    let mut f = File::open(file_name).map_err(|e| e.to_string())?;
-    let mut buffer = [0_u8; ROM_SIZE];
-    f.read(&mut buffer).map_err(|e| e.to_string())?;
+    let file_name = matches.value_of("file_name");
+
+    // The command line goes on top of the configuration file.
+    let scale = match matches.value_of("scale") {
+        Some(scale) => Some(scale.parse().map_err(|_| format!("invalid scale: {}", scale))?),
+        None => None,
+    };
+
+    let number = |name: &str| -> Result<Option<f32>, String> {
+        match matches.value_of(name) {
+            Some(v) => v.parse().map(Some).map_err(|_| format!("invalid {}: {}", name, v)),
+            None => Ok(None),
+        }
+    };
+
+    let flags = Config {
+        video: VideoConfig {
+            scale,
+            integer_scaling: Some(true).filter(|_| matches.is_present("integer_scaling")),
+            fullscreen: Some(true).filter(|_| matches.is_present("fullscreen")),
+            theme: matches.value_of("theme").map(String::from),
+            colors: matches.value_of("colors").map(|c| c.split(',').map(String::from).collect()),
+            background: matches.value_of("bg").map(String::from),
+            foreground: matches.value_of("fg").map(String::from),
+            deflicker: matches.value_of("deflicker").map(String::from),
+            filters: matches.value_of("filters").map(String::from),
+            keypad_overlay: Some(true).filter(|_| matches.is_present("keypad_overlay")),
+            stats_overlay: Some(true).filter(|_| matches.is_present("stats_overlay")),
+        },
+        audio: AudioConfig {
+            frequency: number("tone")?,
+            waveform: matches.value_of("waveform").map(String::from),
+            volume: number("volume")?,
+            muted: Some(true).filter(|_| matches.is_present("mute")),
+        },
+        emulation: EmulationConfig {
+            instructions_per_frame: match matches.value_of("instructions_per_frame") {
+                Some(n) => Some(n.parse().map_err(|_| format!("invalid number of instructions: {}", n))?),
+                None => None,
+            },
+            speed: number("speed")?,
+            fast_forward: number("fast_forward")?,
+            slow_motion: number("slow_motion")?,
+            xo_chip: Some(true).filter(|_| matches.is_present("xo_chip")),
+            platform: matches.value_of("platform").map(String::from),
+        },
+        keymap: matches.value_of("keymap").map(String::from),
+        ..Config::default()
+    };
+    // The standard output can only take one stream at a time. Everything
+    // else the emulator prints goes to the standard error.
+    if matches.value_of("record") == Some("-") && matches.value_of("log_input") == Some("-") {
+        return Err("--record and --log-input cannot both write to the standard output".to_string());
+    }
+
+    let config = match matches.value_of("config") {
+        Some(path) => Some(Config::load(Path::new(path))?),
+        None => match Config::default_path() {
+            Some(path) if path.exists() => Some(Config::load(&path)?),
+            _ => None,
+        },
+    };
+
+    let mut options = Options {
+        config: config.unwrap_or_default(),
+        flags,
+        ..Options::default()
+    };
+    options.record = matches.value_of("record").map(PathBuf::from);
+    options.record_audio = matches.value_of("record_audio").map(PathBuf::from);
+    options.headless = matches.is_present("headless");
+    if let Some(frames) = matches.value_of("frames") {
+        options.frames = frames.parse().map_err(|_| format!("invalid number of frames: {}", frames))?;
+    }
+    if let Some(games) = matches.value_of("games") {
+        options.games_dir = PathBuf::from(games);
+    }
+    options.watch = matches.is_present("watch");
+    options.input_log = matches.value_of("log_input").map(PathBuf::from);
+    options.frame_stats = matches.is_present("frame_stats");
+
+    // The ROM's own settings and its section of the configuration file are
+    // worked out by the library, the same way as for the ROMs picked in
+    // the launcher.
+    let (rom, options) = match file_name {
+        Some(file_name) => {
+            let (rom, options) = rom::open(Path::new(file_name), &options)?;
+            (Some(rom), options)
+        }
+        None => (None, options.for_rom(None, None)?),
+    };
 
-    Emulator::run(buffer)
+    Emulator::run(rom, &options)
 }
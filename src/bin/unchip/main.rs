@@ -41,8 +41,8 @@ fn decode(opcode: u16) {
     let n = (opcode & 0xF) as usize;
 
     let mnemonic = match parts {
-        (0x0, 0x0, 0xE, 0x0) => format!("CLS"),
-        (0x0, 0x0, 0xE, 0xE) => format!("RET"),
+        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
         (0x0, _, _, _)       => format!("SYS {:#05X?} (unsupported)", nnn),
         (0x1, _, _, _)       => format!("JP {:#05X?}", nnn),
         (0x2, _, _, _)       => format!("CALL {:#05X?}", nnn),
@@ -76,7 +76,7 @@ fn decode(opcode: u16) {
         (0xF, _, 0x3, 0x3)   => format!("LD B, V{}", vx),
         (0xF, _, 0x5, 0x5)   => format!("LD [I], V{}", vx),
         (0xF, _, 0x6, 0x5)   => format!("LD V{}, [I]", vx),
-        _ => format!("Unsupported"),
+        _ => "Unsupported".to_string(),
     };
 
     println!("({:#06X?}) {}", opcode, mnemonic);
@@ -90,7 +90,7 @@ fn unchip(rom: [u8; ROM_SIZE], size: usize) {
 }
 
 fn main() -> Result<(), String> {
-    if let Some(file_name) = env::args().skip(1).next() {
+    if let Some(file_name) = env::args().nth(1) {
         let mut f = File::open(file_name).map_err(|e| e.to_string())?;
         let mut buffer = [0_u8; ROM_SIZE];
         let size = f.read(&mut buffer).map_err(|e| e.to_string())?;
@@ -22,13 +22,11 @@
 
 #![allow(non_snake_case)]
 
-use std::ops::{Index,IndexMut};
-
+use super::display::Display;
 use super::mem::{AddressSpace, RESERVED_MEMORY_SIZE};
+use super::quirks::Quirks;
 
-pub(crate) const CHIP8_WIDTH: usize  = 64;
-pub(crate) const CHIP8_HEIGHT: usize = 32;
-const STACK_SIZE: usize              = 16;
+pub(super) const STACK_SIZE: usize = 16;
 
 /// Indicates how the Program Counter will change after a certain
 /// instruction is executed: it may advance to the next instruction,
@@ -49,30 +47,6 @@ impl ProgramCounter {
     }
 }
 
-/// Represents the 64x32 monochrome display. Individual pixels are accessed
-/// by indexing the Display using a tuple (x, y).
-pub(crate) struct Display([u8; CHIP8_HEIGHT * CHIP8_WIDTH]);
-
-impl Default for Display {
-    fn default() -> Self {
-        Display([0_u8; CHIP8_HEIGHT * CHIP8_WIDTH])
-    }
-}
-
-impl Index<(usize, usize)> for Display {
-    type Output = u8;
-
-    fn index(&self, index: (usize, usize)) -> &Self::Output {
-        &self.0[index.1 * CHIP8_WIDTH + index.0]
-    }
-}
-
-impl IndexMut<(usize, usize)> for Display {
-    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
-        &mut self.0[index.1 * CHIP8_WIDTH + index.0]
-    }
-}
-
 pub(crate) struct Cpu {
     pub(super) mem: Box<dyn AddressSpace>,
     /// 16-level stack used to store memory addresses where the interpreter
@@ -85,6 +59,8 @@ pub(crate) struct Cpu {
     pub(super) v_reg: [u8; 16],
     /// 16-bit register used to hold memory addresses.
     pub(super) i: u16,
+    /// Address of the sprite of the digit 0 in the font.
+    pub(super) font_address: u16,
 
     /// Delay timer register. The delay timer is active whenever this register
     /// is non-zero. According to the specs, its value should be substracted
@@ -111,7 +87,18 @@ pub(crate) struct Cpu {
     // | A | 0 | B | F |
     // +---+---+---+---+
     pub keypad: [bool; 16],  // 16-key hexadecimal keypad
+    /// State of the keypad when the previous instruction was executed,
+    /// used to tell when keys go down or up.
+    pub(super) last_keypad: [bool; 16],
+    /// Key that Fx0A saw going down, and is waiting to be released.
+    pub(super) waiting_key: Option<usize>,
+    /// Key that the previous Fx0A returned. Fx0A ignores it until it
+    /// is released, so a key held down is only seen once.
+    pub(super) seen_key: Option<usize>,
     pub display: Display,
+    /// Set in the 64x64 hi-res mode, where 0230 clears the display.
+    pub(super) hires: bool,
+    pub(super) quirks: Quirks,
 
     pub draw: bool,
     pub beep: bool,
@@ -124,12 +111,18 @@ impl Cpu {
             stack: [0_u16; STACK_SIZE],
             v_reg: [0_u8; 16],
             i: 0,
+            font_address: 0,
             delay_t: 0,
             sound_t: 0,
             pc: RESERVED_MEMORY_SIZE as u16, // Initialize the ProgramCounter at 0x200
             sp: 0,
             keypad: [false; 16],
+            last_keypad: [false; 16],
+            waiting_key: None,
+            seen_key: None,
             display: Display::default(),
+            hires: false,
+            quirks: Quirks::default(),
             draw: false,
             beep: false
         }
@@ -139,12 +132,28 @@ impl Cpu {
     /// (located in the address stored in the Program Counter), it decodes the
     /// instruction (parse the operands) and executes it. Since each Opcode is
     /// 2 bytes, the instruction is stored in two adjacent memory addresses:
-    /// PC and PC + 1.
-    pub fn fetch_decode_execute(&mut self) {
-        let opcode = self.mem.read_word(self.pc);
+    /// PC and PC + 1. Fails if the instruction goes outside of the memory
+    /// or the stack, in which case the program can't go on.
+    pub fn fetch_decode_execute(&mut self) -> Result<(), String> {
+        let opcode = self.mem.read_word(self.pc).map_err(|e| format!("0x{:03X}: {}", self.pc, e))?;
+
+        self.pc = self.execute_instruction(opcode)
+            .map_err(|e| format!("{:04X} at 0x{:03X}: {}", opcode, self.pc, e))?;
+        self.last_keypad = self.keypad;
+
+        // The VIP also beeps while Fx0A waits for a key to be released.
+        self.beep = self.sound_t > 0 || self.waiting_key.is_some();
+        Ok(())
+    }
 
-        self.pc = self.execute_instruction(opcode);
+    /// Address of the next instruction to be executed.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
 
+    /// Decrements the delay and sound timers. It must be called 60 times
+    /// per second of emulated time, at the end of every frame.
+    pub fn tick_timers(&mut self) {
         if self.delay_t > 0 {
             self.delay_t -= 1;
         }
@@ -153,10 +162,10 @@ impl Cpu {
             self.sound_t -= 1;
         }
 
-        self.beep = self.sound_t > 0;
+        self.beep = self.sound_t > 0 || self.waiting_key.is_some();
     }
 
-    fn execute_instruction(&mut self, opcode: u16) -> u16 {
+    fn execute_instruction(&mut self, opcode: u16) -> Result<u16, String> {
         let parts = (
             ((opcode & 0xF000) >> 12) as u8,
             ((opcode & 0x0F00) >> 8) as usize,
@@ -172,6 +181,7 @@ impl Cpu {
 
         let new_pc = match parts {
             (0x0, 0x0, 0xE, 0x0) => self.execute_CLS(),
+            (0x0, 0x2, 0x3, 0x0) if self.hires => self.execute_CLS(),
             (0x0, 0x0, 0xE, 0xE) => self.execute_RET(),
             (0x1, _, _, _)       => self.execute_JP_addr(nnn),
             (0x2, _, _, _)       => self.execute_CALL_addr(nnn),
@@ -186,9 +196,9 @@ impl Cpu {
             (0x8, _, _, 0x3)     => self.execute_XOR_Vx_Vy(vx, vy),
             (0x8, _, _, 0x4)     => self.execute_ADD_Vx_Vy(vx, vy),
             (0x8, _, _, 0x5)     => self.execute_SUB_Vx_Vy(vx, vy),
-            (0x8, _, _, 0x6)     => self.execute_SHR_Vx(vx),
+            (0x8, _, _, 0x6)     => self.execute_SHR_Vx(vx, vy),
             (0x8, _, _, 0x7)     => self.execute_SUBN_Vx_Vy(vx, vy),
-            (0x8, _, _, 0xE)     => self.execute_SHL_Vx(vx),
+            (0x8, _, _, 0xE)     => self.execute_SHL_Vx(vx, vy),
             (0x9, _, _, 0x0)     => self.execute_SNE_Vx_Vy(vx, vy),
             (0xA, _, _, _)       => self.execute_LD_I_addr(nnn),
             (0xB, _, _, _)       => self.execute_JP_V0_addr(nnn),
@@ -205,17 +215,28 @@ impl Cpu {
             (0xF, _, 0x3, 0x3)   => self.execute_LD_B_Vx(vx),
             (0xF, _, 0x5, 0x5)   => self.execute_LD_I_Vx(vx),
             (0xF, _, 0x6, 0x5)   => self.execute_LD_Vx_I(vx),
-            _                    => ProgramCounter::Next,
-        };
+            _                    => Ok(ProgramCounter::Next),
+        }?;
 
-        match new_pc {
-            ProgramCounter::Next => self.pc + 2,
-            ProgramCounter::Skip => self.pc + 4,
+        Ok(match new_pc {
+            ProgramCounter::Next => self.pc.wrapping_add(2),
+            ProgramCounter::Skip => self.pc.wrapping_add(4),
             ProgramCounter::Address(addr) => addr,
+        })
+    }
+
+    /// Returns the first key held down, other than the one the previous
+    /// Fx0A returned if it hasn't been released since.
+    pub(super) fn key_down(&mut self) -> Option<usize> {
+        if self.seen_key.is_some_and(|k| !self.keypad[k]) {
+            self.seen_key = None;
         }
+
+        (0..16).find(|&k| self.keypad[k] && self.seen_key != Some(k))
     }
 
-    pub(super) fn is_key_pressed(&self) -> Option<usize> {
-        self.keypad.iter().position(|&k| k)
+    /// Tells whether the key went up since the previous instruction.
+    pub(super) fn key_just_released(&self, k: usize) -> bool {
+        self.last_keypad[k] && !self.keypad[k]
     }
 }
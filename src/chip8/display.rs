@@ -0,0 +1,139 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::ops::{Index,IndexMut};
+use std::path::Path;
+
+use crate::palette::Palette;
+
+pub(crate) const CHIP8_WIDTH: usize  = 64;
+pub(crate) const CHIP8_HEIGHT: usize = 32;
+
+/// Represents the monochrome display, 64x32 unless the platform says
+/// otherwise. Individual pixels are accessed by indexing the Display
+/// using a tuple (x, y).
+///
+/// It is available as `chippy::emulator::Display`, so that other programs
+/// can build an image and save it with `save_png`.
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new(CHIP8_WIDTH, CHIP8_HEIGHT)
+    }
+}
+
+impl Display {
+    pub fn new(width: usize, height: usize) -> Self {
+        Display { width, height, pixels: vec![0_u8; width * height] }
+    }
+
+    /// Width of the display, in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the display, in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns all the pixels, row by row.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Turns all the pixels off.
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = 0);
+    }
+
+    /// Returns the value of every pixel, row by row, with each CHIP-8
+    /// pixel scaled up to a `scale` x `scale` block.
+    pub fn to_indexed(&self, scale: usize) -> Vec<u8> {
+        let width = self.width * scale;
+        let mut buffer = Vec::with_capacity(width * self.height * scale);
+
+        for y in 0..self.height * scale {
+            for x in 0..width {
+                buffer.push(self[(x / scale, y / scale)]);
+            }
+        }
+
+        buffer
+    }
+
+    /// Converts the display into a packed RGB24 buffer, using the given
+    /// palette to pick the colour of each pixel. Every CHIP-8 pixel
+    /// becomes a `scale` x `scale` block in the resulting image.
+    pub fn to_rgb(&self, palette: &Palette, scale: usize) -> Vec<u8> {
+        let indexed = self.to_indexed(scale);
+        let mut buffer = Vec::with_capacity(indexed.len() * 3);
+
+        for v in indexed {
+            let color = palette.color(v);
+            buffer.extend_from_slice(&[color.0, color.1, color.2]);
+        }
+
+        buffer
+    }
+
+    /// Writes the current contents of the display to a PNG file. The
+    /// image is rendered on the CPU, so no access to the window or to
+    /// the GPU is needed.
+    pub fn save_png(&self, path: &Path, palette: &Palette, scale: usize) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(file),
+            (self.width * scale) as u32,
+            (self.height * scale) as u32,
+        );
+        encoder.set_color(png::ColorType::RGB);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.to_rgb(palette, scale)))
+            .map_err(|e| e.to_string())
+    }
+}
+
+impl Index<(usize, usize)> for Display {
+    type Output = u8;
+
+    fn index(&self, index: (usize, usize)) -> &Self::Output {
+        &self.pixels[index.1 * self.width + index.0]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Display {
+    fn index_mut(&mut self, index: (usize, usize)) -> &mut Self::Output {
+        &mut self.pixels[index.1 * self.width + index.0]
+    }
+}
@@ -20,16 +20,25 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-pub(super) const RESERVED_MEMORY_SIZE: usize = 512;
-pub const ROM_SIZE: usize = 3584;
-
+use super::platform::Platform;
+
+pub(crate) const RESERVED_MEMORY_SIZE: usize = 512;
+/// Memory of the original CHIP-8 interpreters.
+pub const MEMORY_SIZE: usize = 4096;
+/// Memory of XO-CHIP, which can address 64 KiB.
+pub const XO_CHIP_MEMORY_SIZE: usize = 65536;
+/// Largest program that fits in the memory of the original interpreters.
+pub const ROM_SIZE: usize = MEMORY_SIZE - RESERVED_MEMORY_SIZE;
+
+/// Memory as the CPU sees it. Accesses outside of it are errors, which
+/// stop the program.
 pub(crate) trait AddressSpace {
-    fn read_byte(&self, addr: u16) -> u8;
-    fn write_byte(&mut self, value: u8, addr: u16);
+    fn read_byte(&self, addr: u16) -> Result<u8, String>;
+    fn write_byte(&mut self, value: u8, addr: u16) -> Result<(), String>;
 
-    fn read_word(&self, addr: u16) -> u16 {
-        (self.read_byte(addr) as u16) << 8 |
-            (self.read_byte(addr+1) as u16)
+    fn read_word(&self, addr: u16) -> Result<u16, String> {
+        Ok((self.read_byte(addr)? as u16) << 8 |
+            (self.read_byte(addr.wrapping_add(1))? as u16))
     }
 }
 
@@ -39,80 +48,96 @@ pub(crate) struct Memory {
 }
 
 impl Memory {
-    pub fn new(rom: Box<dyn AddressSpace>) -> Self {
+    /// Memory laid out like on `platform`, with `rom` at its load
+    /// address.
+    pub fn new(rom: Box<dyn AddressSpace>, platform: &Platform) -> Self {
         Memory {
-            reserved: ReservedMemory::new(),
-            rom: rom
+            reserved: ReservedMemory::new(platform),
+            rom
         }
     }
 }
 
 impl AddressSpace for Memory {
-    fn read_byte(&self, addr: u16) -> u8 {
-        match addr {
-            0x0..=0x1FF => self.reserved.read_byte(addr),
-            _           => self.rom.read_byte(addr - RESERVED_MEMORY_SIZE as u16),
+    fn read_byte(&self, addr: u16) -> Result<u8, String> {
+        match addr as usize {
+            a if a < self.reserved.0.len() => self.reserved.read_byte(addr),
+            _ => self.rom.read_byte(addr - self.reserved.0.len() as u16)
+                .map_err(|_| out_of_memory(addr)),
         }
     }
 
-    fn write_byte(&mut self, value: u8, addr: u16) {
-        match addr {
-            0x0..=0x1FF => self.reserved.write_byte(value, addr),
-            _           => self.rom.write_byte(value, addr - RESERVED_MEMORY_SIZE as u16),
+    fn write_byte(&mut self, value: u8, addr: u16) -> Result<(), String> {
+        match addr as usize {
+            a if a < self.reserved.0.len() => self.reserved.write_byte(value, addr),
+            _ => self.rom.write_byte(value, addr - self.reserved.0.len() as u16)
+                .map_err(|_| out_of_memory(addr)),
         }
     }
 }
 
-struct ReservedMemory([u8; RESERVED_MEMORY_SIZE]);
+fn out_of_memory(addr: u16) -> String {
+    format!("address 0x{:03X} is past the end of the memory", addr)
+}
+
+/// Memory below the load address, which holds the font.
+struct ReservedMemory(Vec<u8>);
 
 impl Default for ReservedMemory {
-    fn default() -> Self { ReservedMemory([0_u8; RESERVED_MEMORY_SIZE]) }
+    fn default() -> Self { ReservedMemory(vec![0_u8; RESERVED_MEMORY_SIZE]) }
 }
 
 impl ReservedMemory {
-    fn new() -> Self {
-        let mut rs = ReservedMemory([0_u8; RESERVED_MEMORY_SIZE]);
-        (0..80).for_each(|i| rs.0[i] = FONT_DATA[i]);
+    fn new(platform: &Platform) -> Self {
+        let mut rs = ReservedMemory(vec![0_u8; platform.load_address]);
+        let font = platform.font_address;
+        rs.0[font..font + FONT_DATA.len()].copy_from_slice(&FONT_DATA);
 
         rs
     }
 }
 
 impl AddressSpace for ReservedMemory {
-    fn read_byte(&self, addr: u16) -> u8 {
-        self.0[addr as usize]
+    fn read_byte(&self, addr: u16) -> Result<u8, String> {
+        self.0.get(addr as usize).copied().ok_or_else(|| out_of_memory(addr))
     }
 
-    fn write_byte(&mut self, _value: u8, _addr: u16) {
-        panic!("read-only memory")
+    fn write_byte(&mut self, _value: u8, addr: u16) -> Result<(), String> {
+        Err(format!("address 0x{:03X} is read-only", addr))
     }
 }
 
-pub(crate) struct Rom([u8; ROM_SIZE]);
+pub(crate) struct Rom(Vec<u8>);
 
 impl Default for Rom {
-    fn default() -> Self { Rom([0_u8; ROM_SIZE]) }
+    fn default() -> Self { Rom(vec![0_u8; ROM_SIZE]) }
 }
 
 impl Rom {
-    pub fn new(rom: [u8; ROM_SIZE]) -> Self {
+    /// Memory past the reserved area of `platform`, with the program at
+    /// the start.
+    pub fn new(program: &[u8], platform: &Platform) -> Self {
+        let mut rom = vec![0_u8; platform.max_program_size()];
+        rom[..program.len()].copy_from_slice(program);
         Rom(rom)
     }
 }
 
 impl AddressSpace for Rom {
-    fn read_byte(&self, addr: u16) -> u8 {
-        self.0[addr as usize]
+    fn read_byte(&self, addr: u16) -> Result<u8, String> {
+        self.0.get(addr as usize).copied().ok_or_else(|| out_of_memory(addr))
     }
 
-    fn write_byte(&mut self, value: u8, addr: u16) {
-        self.0[addr as usize] = value;
+    fn write_byte(&mut self, value: u8, addr: u16) -> Result<(), String> {
+        let byte = self.0.get_mut(addr as usize).ok_or_else(|| out_of_memory(addr))?;
+        *byte = value;
+        Ok(())
     }
 }
 
 // Preloaded sprite data representing a font of sixteen
 // hexadecimal digits.
-const FONT_DATA: [u8; 80] = [
+pub(crate) const FONT_DATA: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -21,37 +21,58 @@
 // SOFTWARE.
 
 pub mod cpu;
+pub mod display;
 pub mod mem;
 mod opcodes;
+pub mod platform;
+pub mod quirks;
 
 use cpu::Cpu;
+use display::Display;
 use mem::{Memory,Rom};
+use platform::Platform;
+use quirks::Quirks;
 
-pub(crate) fn new_chip8(rom: [u8; mem::ROM_SIZE]) -> Cpu {
-    Cpu::new(Box::new(Memory::new(Box::new(Rom::new(rom)))))
+pub(crate) fn new_chip8(program: &[u8], platform: &Platform, quirks: Quirks) -> Cpu {
+    let rom = Rom::new(program, platform);
+    let mut cpu = Cpu::new(Box::new(Memory::new(Box::new(rom), platform)));
+    cpu.pc = platform.start_address as u16;
+    cpu.hires = platform.is_hires();
+    cpu.font_address = platform.font_address as u16;
+    cpu.display = Display::new(platform.display_size.0, platform.display_size.1);
+    cpu.quirks = quirks;
+    cpu
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+
     use super::cpu::Cpu;
+    use super::display::{CHIP8_HEIGHT,CHIP8_WIDTH,Display};
     use super::mem::{AddressSpace,Memory,RESERVED_MEMORY_SIZE};
+    use super::new_chip8;
+    use super::platform::Platform;
+    use super::quirks::Quirks;
+    use crate::palette::{Palette,Rgb};
 
     struct DummyRom;
 
     impl AddressSpace for DummyRom {
-        fn read_byte(&self, _addr: u16) -> u8 { 0 }
-        fn write_byte(&mut self, _value: u8, _addr: u16) {}
+        fn read_byte(&self, _addr: u16) -> Result<u8, String> { Ok(0) }
+        fn write_byte(&mut self, _value: u8, _addr: u16) -> Result<(), String> { Ok(()) }
     }
 
     struct TestRom([u8; 20]);
 
     impl AddressSpace for TestRom {
-        fn read_byte(&self, addr: u16) -> u8 {
-            self.0[addr as usize]
+        fn read_byte(&self, addr: u16) -> Result<u8, String> {
+            Ok(self.0[addr as usize])
         }
 
-        fn write_byte(&mut self, value: u8, addr: u16) {
+        fn write_byte(&mut self, value: u8, addr: u16) -> Result<(), String> {
             self.0[addr as usize] = value;
+            Ok(())
         }
     }
 
@@ -70,64 +91,274 @@ mod tests {
 
     #[test]
     fn test_memory_mapper() {
-        let mut mm = Memory::new(Box::new(TestRom(TEST_ROM)));
+        let mut mm = Memory::new(Box::new(TestRom(TEST_ROM)), &Platform::default());
 
-        assert_eq!(0x61, mm.read_byte(RESERVED_MEMORY_SIZE as u16));
-        assert_eq!(0x6101, mm.read_word(RESERVED_MEMORY_SIZE as u16));
+        assert_eq!(Ok(0x61), mm.read_byte(RESERVED_MEMORY_SIZE as u16));
+        assert_eq!(Ok(0x6101), mm.read_word(RESERVED_MEMORY_SIZE as u16));
 
-        mm.write_byte(0xF, RESERVED_MEMORY_SIZE as u16);
-        assert_eq!(0xF, mm.read_byte(RESERVED_MEMORY_SIZE as u16));
+        mm.write_byte(0xF, RESERVED_MEMORY_SIZE as u16).unwrap();
+        assert_eq!(Ok(0xF), mm.read_byte(RESERVED_MEMORY_SIZE as u16));
     }
 
     #[test]
-    #[should_panic]
     fn test_write_to_reserved_memory() {
-        let mut mm = Memory::new(Box::new(DummyRom));
-        mm.write_byte(0xF, 0x0);
+        let mut mm = Memory::new(Box::new(DummyRom), &Platform::default());
+        assert!(mm.write_byte(0xF, 0x0).unwrap_err().contains("read-only"));
+    }
+
+    #[test]
+    fn test_memory_and_stack_errors() {
+        let run = |rom: &[u8], instructions| {
+            let mut cpu = new_chip8(rom, &Platform::default(), Quirks::default());
+            (0..instructions).try_for_each(|_| cpu.fetch_decode_execute())
+        };
+
+        // Reading V0 and V1 from 0xFFF goes past the end of the memory.
+        let error = run(&[0xAF, 0xFF, 0xF1, 0x65], 2).unwrap_err();
+        assert_eq!("F165 at 0x202: address 0x1000 is past the end of the memory", error);
+        assert!(run(&[0xAF, 0xFF, 0xF0, 0x65], 2).is_ok());
+        // So does fetching an instruction from there.
+        assert!(run(&[0x1F, 0xFF], 2).unwrap_err().contains("0x1000"));
+        assert!(run(&[0xA0, 0x00, 0xF0, 0x55], 2).unwrap_err().contains("read-only"));
+
+        // A subroutine that calls itself overflows the stack, and a return
+        // without a call underflows it.
+        assert!(run(&[0x22, 0x00], 16).is_ok());
+        assert!(run(&[0x22, 0x00], 17).unwrap_err().contains("nested subroutine calls"));
+        assert!(run(&[0x00, 0xEE], 1).unwrap_err().contains("return without"));
     }
 
     #[test]
     fn test_run_rom() {
-        let mut cpu = Cpu::new(Box::new(Memory::new(Box::new(TestRom(TEST_ROM)))));
+        let mut cpu = Cpu::new(Box::new(Memory::new(Box::new(TestRom(TEST_ROM)), &Platform::default())));
 
         assert_eq!(0x200, cpu.pc);
-        assert_eq!(0x61, cpu.mem.read_byte(cpu.pc));
+        assert_eq!(Ok(0x61), cpu.mem.read_byte(cpu.pc));
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x202, cpu.pc);
         assert_eq!(0x1, cpu.v_reg[0x1]);
 
         for i in 0..=253 {
-            cpu.fetch_decode_execute();
+            cpu.fetch_decode_execute().unwrap();
             assert_eq!(0x204, cpu.pc);
             assert_eq!(0x2 + (i as u8), cpu.v_reg[0x1]);
 
-            cpu.fetch_decode_execute();
+            cpu.fetch_decode_execute().unwrap();
             assert_eq!(0x206, cpu.pc);
 
-            cpu.fetch_decode_execute();
+            cpu.fetch_decode_execute().unwrap();
             assert_eq!(cpu.pc, 0x202);
         }
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x204, cpu.pc);
         assert_eq!(0x0, cpu.v_reg[0x1]);
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x208, cpu.pc);
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x20A, cpu.pc);
         assert_eq!(0x1, cpu.v_reg[0x1]);
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x20C, cpu.pc);
         assert_eq!(0xFF, cpu.v_reg[0x2]);
 
-        cpu.fetch_decode_execute();
+        cpu.fetch_decode_execute().unwrap();
         assert_eq!(0x20E, cpu.pc);
         assert_eq!(0x0, cpu.v_reg[0x1]);
         assert_eq!(0xFF, cpu.v_reg[0x2]);
         assert_eq!(0x1, cpu.v_reg[0xF]);
     }
+
+    // Fx0A with V3, in a loop.
+    fn wait_for_key_rom() -> Vec<u8> {
+        vec![0xF3, 0x0A, 0x12, 0x00]
+    }
+
+    #[test]
+    fn test_wait_for_key_release() {
+        let quirks = Quirks { key_release: true, ..Quirks::default() };
+        let mut cpu = new_chip8(&wait_for_key_rom(), &Platform::default(), quirks);
+
+        cpu.fetch_decode_execute().unwrap();
+        cpu.keypad[0x7] = true;
+        cpu.fetch_decode_execute().unwrap();
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x200, cpu.pc);
+        assert!(cpu.beep);
+
+        cpu.keypad[0x7] = false;
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x7, cpu.v_reg[0x3]);
+        assert!(!cpu.beep);
+    }
+
+    #[test]
+    fn test_wait_for_key_press() {
+        let mut cpu = new_chip8(&wait_for_key_rom(), &Platform::default(), Quirks::default());
+
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x200, cpu.pc);
+
+        cpu.keypad[0xB] = true;
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0xB, cpu.v_reg[0x3]);
+
+        // Holding the key down doesn't make the next Fx0A return.
+        cpu.fetch_decode_execute().unwrap();
+        cpu.fetch_decode_execute().unwrap();
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x200, cpu.pc);
+    }
+
+    #[test]
+    fn test_wait_for_key_held_before() {
+        let mut cpu = new_chip8(&wait_for_key_rom(), &Platform::default(), Quirks::default());
+        cpu.keypad[0x4] = true;
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x4, cpu.v_reg[0x3]);
+
+        let quirks = Quirks { key_release: true, ..Quirks::default() };
+        let mut cpu = new_chip8(&wait_for_key_rom(), &Platform::default(), quirks);
+        cpu.keypad[0x4] = true;
+        cpu.fetch_decode_execute().unwrap();
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x200, cpu.pc);
+        assert!(cpu.beep);
+
+        cpu.keypad[0x4] = false;
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0x202, cpu.pc);
+        assert_eq!(0x4, cpu.v_reg[0x3]);
+    }
+
+    #[test]
+    fn test_shift_and_logic_quirks() {
+        // V1 = 0x81, V2 = 0x06, V1 |= V2, V1 >>= 1 (with V2).
+        let rom = [0x61, 0x81, 0x62, 0x06, 0x6F, 0x55, 0x81, 0x21, 0x81, 0x26];
+        let mut cpu = new_chip8(&rom, &Platform::default(), Quirks::default());
+        (0..5).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!((0x43, 0x1), (cpu.v_reg[0x1], cpu.v_reg[0xF]));
+
+        let quirks = Quirks { shift: false, logic: true, ..Quirks::default() };
+        let mut cpu = new_chip8(&rom, &Platform::default(), quirks);
+        (0..4).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!(0x0, cpu.v_reg[0xF]);
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!((0x03, 0x0), (cpu.v_reg[0x1], cpu.v_reg[0xF]));
+    }
+
+    #[test]
+    fn test_load_store_and_jump_quirks() {
+        // I = 0x300, store V0 to V2, V0 = 4, V2 = 8, jump to 0x220 + V0 or V2.
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0x60, 0x04, 0x62, 0x08, 0xB2, 0x20];
+        let mut cpu = new_chip8(&rom, &Platform::default(), Quirks::default());
+        (0..5).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!((0x300, 0x224), (cpu.i, cpu.pc));
+
+        let quirks = Quirks { load_store: false, jump: true, ..Quirks::default() };
+        let mut cpu = new_chip8(&rom, &Platform::default(), quirks);
+        (0..5).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!((0x303, 0x228), (cpu.i, cpu.pc));
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        // Draws the 0 font sprite at (62, 30).
+        let rom = [0x60, 0x3E, 0x61, 0x1E, 0xA0, 0x00, 0xD0, 0x15];
+        let mut cpu = new_chip8(&rom, &Platform::default(), Quirks::default());
+        (0..4).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!(1, cpu.display[(62, 30)]);
+        assert_eq!(1, cpu.display[(0, 30)]);
+
+        let quirks = Quirks { clip: true, ..Quirks::default() };
+        let mut cpu = new_chip8(&rom, &Platform::default(), quirks);
+        (0..4).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!(1, cpu.display[(62, 30)]);
+        assert_eq!(0, cpu.display[(0, 30)]);
+    }
+
+    #[test]
+    fn test_platform() {
+        // V0 = 0xA, point I at its sprite and draw it at (60, 46).
+        let rom = [0x60, 0x0A, 0xF0, 0x29, 0x61, 0x3C, 0x62, 0x2E, 0xD1, 0x25];
+        let platform = Platform {
+            load_address: 0x600,
+            start_address: 0x600,
+            font_address: 0x50,
+            display_size: (64, 48),
+            ..Platform::default()
+        };
+        let mut cpu = new_chip8(&rom, &platform, Quirks::default());
+        assert_eq!(0x600, cpu.pc);
+        assert_eq!(Ok(0x60), cpu.mem.read_byte(0x600));
+
+        (0..5).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!(0x50 + 0xA * 5, cpu.i);
+        assert_eq!((64, 48), (cpu.display.width(), cpu.display.height()));
+        // The bottom rows of the sprite wrap around to the top.
+        assert_eq!(1, cpu.display[(60, 46)]);
+        assert_eq!(1, cpu.display[(60, 0)]);
+    }
+
+    #[test]
+    fn test_hires() {
+        // Jumps to the patched interpreter, and then the program at 0x2C0
+        // draws the 0 font sprite at (0, 40) and clears the display.
+        let mut rom = vec![0x12, 0x60];
+        rom.resize(0xC0, 0);
+        rom.extend_from_slice(&[0x60, 0x00, 0x61, 0x28, 0xA0, 0x00, 0xD0, 0x15, 0x02, 0x30]);
+
+        let mut cpu = new_chip8(&rom, &Platform::default().for_program(&rom), Quirks::default());
+        assert_eq!(0x2C0, cpu.pc);
+        (0..4).for_each(|_| cpu.fetch_decode_execute().unwrap());
+        assert_eq!((64, 64), (cpu.display.width(), cpu.display.height()));
+        assert_eq!(1, cpu.display[(0, 40)]);
+
+        cpu.fetch_decode_execute().unwrap();
+        assert_eq!(0, cpu.display[(0, 40)]);
+    }
+
+    #[test]
+    fn test_display_to_rgb() {
+        let mut display = Display::default();
+        display[(1, 0)] = 1;
+
+        let mut palette = Palette::default();
+        palette.colors[0] = Rgb(1, 2, 3);
+        palette.colors[1] = Rgb(4, 5, 6);
+        let rgb = display.to_rgb(&palette, 2);
+
+        assert_eq!(CHIP8_WIDTH * 2 * CHIP8_HEIGHT * 2 * 3, rgb.len());
+        assert_eq!(&[1, 2, 3, 1, 2, 3, 4, 5, 6, 4, 5, 6], &rgb[..12]);
+
+        let second_row = CHIP8_WIDTH * 2 * 3;
+        assert_eq!(&[1, 2, 3, 1, 2, 3, 4, 5, 6], &rgb[second_row..second_row + 9]);
+    }
+
+    #[test]
+    fn test_display_save_png() {
+        let mut display = Display::default();
+        display[(0, 0)] = 1;
+
+        let path = std::env::temp_dir().join("chippy_test_display_save_png.png");
+        display.save_png(&path, &Palette::default(), 3).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let (info, mut reader) = decoder.read_info().unwrap();
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer).unwrap();
+
+        assert_eq!((CHIP8_WIDTH as u32 * 3, CHIP8_HEIGHT as u32 * 3), (info.width, info.height));
+        assert_eq!(&[255, 255, 0], &buffer[..3]);
+        assert_eq!(&[0, 0, 0], &buffer[9..12]);
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
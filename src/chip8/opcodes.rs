@@ -22,188 +22,215 @@
 
 #![allow(non_snake_case)]
 
-use std::mem;
-
-use super::cpu::{
-    CHIP8_HEIGHT,
-    CHIP8_WIDTH,
-    Cpu,
-    ProgramCounter
-};
+use super::cpu::{Cpu,ProgramCounter,STACK_SIZE};
 
 const SPRITE_SIZE: u8 = 5; // size in bytes
 
 impl Cpu {
     // 00E0 - CLS
     // Clear the display.
-    pub(super) fn execute_CLS(&mut self) -> ProgramCounter {
-        mem::take(&mut self.display);
+    pub(super) fn execute_CLS(&mut self) -> Result<ProgramCounter, String> {
+        self.display.clear();
         self.draw = true;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 00EE - RET
     // Return from a subroutine.
-    pub(super) fn execute_RET(&mut self) -> ProgramCounter {
+    pub(super) fn execute_RET(&mut self) -> Result<ProgramCounter, String> {
+        if self.sp == 0 {
+            return Err("return without a subroutine call".to_string());
+        }
         self.sp -= 1;
         let addr = self.stack[self.sp];
-        ProgramCounter::Address(addr)
+        Ok(ProgramCounter::Address(addr))
     }
 
     // 1nnn - JP addr
     // Jump to location nnn.
-    pub(super) fn execute_JP_addr(&mut self, nnn: u16) -> ProgramCounter {
-        ProgramCounter::Address(nnn)
+    pub(super) fn execute_JP_addr(&mut self, nnn: u16) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::Address(nnn))
     }
 
     // 2nnn - CALL addr
     // Call subroutine at nnn.
-    pub(super) fn execute_CALL_addr(&mut self, nnn: u16) -> ProgramCounter {
-        self.stack[self.sp] = self.pc + 2;
+    pub(super) fn execute_CALL_addr(&mut self, nnn: u16) -> Result<ProgramCounter, String> {
+        if self.sp == STACK_SIZE {
+            return Err(format!("more than {} nested subroutine calls", STACK_SIZE));
+        }
+        self.stack[self.sp] = self.pc.wrapping_add(2);
         self.sp += 1;
-        ProgramCounter::Address(nnn)
+        Ok(ProgramCounter::Address(nnn))
     }
 
     // 3xkk - SE Vx, byte
     // Skip next instruction if Vx = kk.
-    pub(super) fn execute_SE_Vx_kk(&mut self, vx: usize, kk: u8) -> ProgramCounter {
-        ProgramCounter::skip_if(self.v_reg[vx] == kk)
+    pub(super) fn execute_SE_Vx_kk(&mut self, vx: usize, kk: u8) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(self.v_reg[vx] == kk))
     }
 
     // 4xkk - SNE Vx, byte
     // Skip next instruction if Vx != kk.
-    pub(super) fn execute_SNE_Vx_kk(&mut self, vx: usize, kk: u8) -> ProgramCounter {
-        ProgramCounter::skip_if(self.v_reg[vx] != kk)
+    pub(super) fn execute_SNE_Vx_kk(&mut self, vx: usize, kk: u8) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(self.v_reg[vx] != kk))
     }
 
     // 5xy0 - SE Vx, Vy
     // Skip next instruction if Vx = Vy.
-    pub(super) fn execute_SE_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
-        ProgramCounter::skip_if(self.v_reg[vx] == self.v_reg[vy])
+    pub(super) fn execute_SE_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(self.v_reg[vx] == self.v_reg[vy]))
     }
 
     // 6xkk - LD Vx, byte
     // Set Vx = kk.
-    pub(super) fn execute_LD_Vx_kk(&mut self, vx: usize, kk: u8) -> ProgramCounter {
+    pub(super) fn execute_LD_Vx_kk(&mut self, vx: usize, kk: u8) -> Result<ProgramCounter, String> {
         self.v_reg[vx] = kk;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 7xkk - ADD Vx, byte
     // Set Vx = Vx + kk.
-    pub(super) fn execute_ADD_Vx_kk(&mut self, vx: usize, kk: u8) -> ProgramCounter {
+    pub(super) fn execute_ADD_Vx_kk(&mut self, vx: usize, kk: u8) -> Result<ProgramCounter, String> {
         self.v_reg[vx] = self.v_reg[vx].wrapping_add(kk);
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy0 - LD Vx, Vy
     // Set Vx = Vy.
-    pub(super) fn execute_LD_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_LD_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         self.v_reg[vx] = self.v_reg[vy];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy1 - OR Vx, Vy
     // Set Vx = Vx OR Vy.
-    pub(super) fn execute_OR_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_OR_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         self.v_reg[vx] |= self.v_reg[vy];
-        ProgramCounter::Next
+        self.reset_flag();
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy2 - AND Vx, Vy
     // Set Vx = Vx AND Vy.
-    pub(super) fn execute_AND_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_AND_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         self.v_reg[vx] &= self.v_reg[vy];
-        ProgramCounter::Next
+        self.reset_flag();
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy3 - XOR Vx, Vy
     // Set Vx = Vx XOR Vy.
-    pub(super) fn execute_XOR_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_XOR_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         self.v_reg[vx] ^= self.v_reg[vy];
-        ProgramCounter::Next
+        self.reset_flag();
+        Ok(ProgramCounter::Next)
+    }
+
+    // The logical operations reset VF on the VIP.
+    fn reset_flag(&mut self) {
+        if self.quirks.logic {
+            self.v_reg[0xF] = 0;
+        }
+    }
+
+    // The value shifted by 8xy6 and 8xyE.
+    fn shifted(&self, vx: usize, vy: usize) -> u8 {
+        if self.quirks.shift { self.v_reg[vx] } else { self.v_reg[vy] }
     }
 
     // 8xy4 - ADD Vx, Vy
     // Set Vx = Vx + Vy, set VF = carry.
-    pub(super) fn execute_ADD_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_ADD_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         let (v, of) = self.v_reg[vx].overflowing_add(self.v_reg[vy]);
         self.v_reg[vx] = v;
         self.v_reg[0xF] = of as u8;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy5 - SUB Vx, Vy
     // Set Vx = Vx - Vy, set VF = NOT borrow.
-    pub(super) fn execute_SUB_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_SUB_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         let (v, of) = self.v_reg[vx].overflowing_sub(self.v_reg[vy]);
         self.v_reg[vx] = v;
         self.v_reg[0xF] = !of as u8;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy6 - SHR Vx {, Vy}
     // Set Vx = Vx SHR 1.
-    pub(super) fn execute_SHR_Vx(&mut self, vx: usize) -> ProgramCounter {
-        self.v_reg[0xF] = self.v_reg[vx] & 0x1;
-        self.v_reg[vx] >>= 1;
-        ProgramCounter::Next
+    pub(super) fn execute_SHR_Vx(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
+        let value = self.shifted(vx, vy);
+        self.v_reg[0xF] = value & 0x1;
+        self.v_reg[vx] = value >> 1;
+        Ok(ProgramCounter::Next)
     }
 
     // 8xy7 - SUBN Vx, Vy
     // Set Vx = Vy - Vx, set VF = NOT borrow.
-    pub(super) fn execute_SUBN_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
+    pub(super) fn execute_SUBN_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
         let (v, of) = self.v_reg[vy].overflowing_sub(self.v_reg[vx]);
         self.v_reg[vx] = v;
         self.v_reg[0xF] = !of as u8;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // 8xyE - SHL Vx {, Vy}
     // Set Vx = Vx SHL 1.
-    pub(super) fn execute_SHL_Vx(&mut self, vx: usize) -> ProgramCounter {
-        self.v_reg[0xF] = (self.v_reg[vx] & 0x80) >> 7;
-        self.v_reg[vx] <<= 1;
-        ProgramCounter::Next
+    pub(super) fn execute_SHL_Vx(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
+        let value = self.shifted(vx, vy);
+        self.v_reg[0xF] = (value & 0x80) >> 7;
+        self.v_reg[vx] = value << 1;
+        Ok(ProgramCounter::Next)
     }
 
     // 9xy0 - SNE Vx, Vy
     // Skip next instruction if Vx != Vy.
-    pub(super) fn execute_SNE_Vx_Vy(&mut self, vx: usize, vy: usize) -> ProgramCounter {
-        ProgramCounter::skip_if(self.v_reg[vx] != self.v_reg[vy])
+    pub(super) fn execute_SNE_Vx_Vy(&mut self, vx: usize, vy: usize) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(self.v_reg[vx] != self.v_reg[vy]))
     }
 
     // Annn - LD I, addr
     // Set I = nnn.
-    pub(super) fn execute_LD_I_addr(&mut self, nnn: u16) -> ProgramCounter {
+    pub(super) fn execute_LD_I_addr(&mut self, nnn: u16) -> Result<ProgramCounter, String> {
         self.i = nnn;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Bnnn - JP V0, addr
-    // Jump to location nnn + V0.
-    pub(super) fn execute_JP_V0_addr(&mut self, nnn: u16) -> ProgramCounter {
-        ProgramCounter::Address(nnn + (self.v_reg[0x0] as u16))
+    // Jump to location nnn + V0, or to xnn + Vx with the jump quirk.
+    pub(super) fn execute_JP_V0_addr(&mut self, nnn: u16) -> Result<ProgramCounter, String> {
+        let x = if self.quirks.jump { (nnn >> 8) as usize } else { 0 };
+        Ok(ProgramCounter::Address(nnn + (self.v_reg[x] as u16)))
     }
 
     // Cxkk - RND Vx, byte
     // Set Vx = random byte AND kk.
-    pub(super) fn execute_RND_Vx_kk(&mut self, vx: usize, kk: u8) -> ProgramCounter {
+    pub(super) fn execute_RND_Vx_kk(&mut self, vx: usize, kk: u8) -> Result<ProgramCounter, String> {
         self.v_reg[vx] = kk & rand::random::<u8>();
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Dxyn - DRW Vx, Vy, nibble
     // Display n-byte sprite starting at memory location I
     // at (Vx, Vy), set VF = collision.
-    pub(super) fn execute_DRW_Vx_Vy_n(&mut self, vx: usize, vy: usize, n: usize) -> ProgramCounter {
+    pub(super) fn execute_DRW_Vx_Vy_n(&mut self, vx: usize, vy: usize, n: usize) -> Result<ProgramCounter, String> {
         self.v_reg[0xF] = 0x0;
 
+        // The sprite starts on the display even if Vx or Vy are past its
+        // edges, but with the clip quirk the rest doesn't wrap around.
+        let (width, height) = (self.display.width(), self.display.height());
+        let x = self.v_reg[vx] as usize % width;
+        let y = self.v_reg[vy] as usize % height;
+
         for row in 0..n {
             for col in 0..8 {
-                let dx = (col + self.v_reg[vx] as usize) % CHIP8_WIDTH;
-                let dy = (row + self.v_reg[vy] as usize) % CHIP8_HEIGHT;
-                let color = (self.mem.read_byte(self.i + (row as u16)) >> (7 - col)) & 1;
+                if self.quirks.clip && (x + col >= width || y + row >= height) {
+                    continue;
+                }
+
+                let dx = (x + col) % width;
+                let dy = (y + row) % height;
+                let color = (self.mem.read_byte(self.i.wrapping_add(row as u16))? >> (7 - col)) & 1;
                 self.v_reg[0xF] |= color & self.display[(dx, dy)];
                 self.display[(dx, dy)] ^= color;
             }
@@ -211,94 +238,118 @@ impl Cpu {
 
         self.draw = true;
 
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Ex9E - SKP Vx
     // Skip next instruction if key with the value of Vx is pressed.
-    pub(super) fn execute_SKP_Vx(&mut self, vx: usize) -> ProgramCounter {
-        ProgramCounter::skip_if(self.keypad[self.v_reg[vx] as usize])
+    pub(super) fn execute_SKP_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(self.keypad[(self.v_reg[vx] & 0xF) as usize]))
     }
 
     // ExA1 - SKNP Vx
     // Skip next instruction if key with the value of Vx is not pressed.
-    pub(super) fn execute_SKNP_Vx(&mut self, vx: usize) -> ProgramCounter {
-        ProgramCounter::skip_if(!self.keypad[self.v_reg[vx] as usize])
+    pub(super) fn execute_SKNP_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        Ok(ProgramCounter::skip_if(!self.keypad[(self.v_reg[vx] & 0xF) as usize]))
     }
 
     // Fx07 - LD Vx, DT
     // Set Vx = delay timer value.
-    pub(super) fn execute_LD_Vx_DT(&mut self, vx: usize) -> ProgramCounter {
+    pub(super) fn execute_LD_Vx_DT(&mut self, vx: usize) -> Result<ProgramCounter, String> {
         self.v_reg[vx] = self.delay_t;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx0A - LD Vx, K
-    // Wait for a key press, store the value of the key in Vx.
-    pub(super) fn execute_LD_Vx_K(&mut self, vx: usize) -> ProgramCounter {
-        if let Some(i) = self.is_key_pressed() {
-            self.v_reg[vx] = i as u8;
-            ProgramCounter::Next
-        } else {
-            ProgramCounter::Address(self.pc)
+    // Wait for a key press, store the value of the key in Vx. With the
+    // key_release quirk, like on the VIP, it also waits for the key to be
+    // released, so a single tap is never seen twice.
+    pub(super) fn execute_LD_Vx_K(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        let key = match self.waiting_key {
+            Some(k) if self.key_just_released(k) => Some(k),
+            Some(_) => None,
+            None => match self.key_down() {
+                Some(k) if self.quirks.key_release => {
+                    self.waiting_key = Some(k);
+                    None
+                }
+                key => key,
+            },
+        };
+
+        match key {
+            Some(k) => {
+                self.waiting_key = None;
+                self.seen_key = Some(k);
+                self.v_reg[vx] = k as u8;
+                Ok(ProgramCounter::Next)
+            }
+            None => Ok(ProgramCounter::Address(self.pc)),
         }
     }
 
     // Fx15 - LD DT, Vx
     // Set delay timer = Vx.
-    pub(super) fn execute_LD_DT_Vx(&mut self, vx: usize) -> ProgramCounter {
+    pub(super) fn execute_LD_DT_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
         self.delay_t = self.v_reg[vx];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx18 - LD ST, Vx
     // Set sound timer = Vx.
-    pub(super) fn execute_LD_ST_Vx(&mut self, vx: usize) -> ProgramCounter {
+    pub(super) fn execute_LD_ST_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
         self.sound_t = self.v_reg[vx];
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx1E - ADD I, Vx
     // Set I = I + Vx.
-    pub(super) fn execute_ADD_I_Vx(&mut self, vx: usize) -> ProgramCounter {
-        let v = self.i + (self.v_reg[vx] as u16);
+    pub(super) fn execute_ADD_I_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        let v = self.i.wrapping_add(self.v_reg[vx] as u16);
         self.v_reg[0xF] = (v > 0xF00) as u8;
         self.i = v;
-        ProgramCounter::Next
+        Ok(ProgramCounter::Next)
     }
 
     // Fx29 - LD F, Vx
     // Set I = location of sprite for digit Vx.
-    pub(super) fn execute_LD_F_Vx(&mut self, vx: usize) -> ProgramCounter {
-        self.i = (self.v_reg[vx] * SPRITE_SIZE) as u16;
-        ProgramCounter::Next
+    pub(super) fn execute_LD_F_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        // Only the low nibble counts, like on the VIP.
+        self.i = self.font_address + ((self.v_reg[vx] & 0xF) * SPRITE_SIZE) as u16;
+        Ok(ProgramCounter::Next)
     }
 
     // Fx33 - LD B, Vx
     // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-    pub(super) fn execute_LD_B_Vx(&mut self, vx: usize) -> ProgramCounter {
+    pub(super) fn execute_LD_B_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
         let value_x = self.v_reg[vx];
-        self.mem.write_byte(value_x / 100, self.i);
-        self.mem.write_byte((value_x % 100) / 10, self.i + 1);
-        self.mem.write_byte(value_x % 10, self.i + 2);
-        ProgramCounter::Next
+        self.mem.write_byte(value_x / 100, self.i)?;
+        self.mem.write_byte((value_x % 100) / 10, self.i.wrapping_add(1))?;
+        self.mem.write_byte(value_x % 10, self.i.wrapping_add(2))?;
+        Ok(ProgramCounter::Next)
     }
 
     // Fx55 - LD [I], Vx
     // Store registers V0 through Vx in memory starting at location I.
-    pub(super) fn execute_LD_I_Vx(&mut self, vx: usize) -> ProgramCounter {
-        (0..=vx).for_each(|i| {
-            self.mem.write_byte(self.v_reg[i], self.i + (i as u16));
-        });
-        ProgramCounter::Next
+    pub(super) fn execute_LD_I_Vx(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        for i in 0..=vx {
+            self.mem.write_byte(self.v_reg[i], self.i.wrapping_add(i as u16))?;
+        }
+        if !self.quirks.load_store {
+            self.i = self.i.wrapping_add(vx as u16 + 1);
+        }
+        Ok(ProgramCounter::Next)
     }
 
     // Fx65 - LD Vx, [I]
     // Read registers V0 through Vx from memory starting at location I.
-    pub(super) fn execute_LD_Vx_I(&mut self, vx: usize) -> ProgramCounter {
-        (0..=vx).for_each(|i| {
-            self.v_reg[i] = self.mem.read_byte(self.i + (i as u16));
-        });
-        ProgramCounter::Next
+    pub(super) fn execute_LD_Vx_I(&mut self, vx: usize) -> Result<ProgramCounter, String> {
+        for i in 0..=vx {
+            self.v_reg[i] = self.mem.read_byte(self.i.wrapping_add(i as u16))?;
+        }
+        if !self.quirks.load_store {
+            self.i = self.i.wrapping_add(vx as u16 + 1);
+        }
+        Ok(ProgramCounter::Next)
     }
 }
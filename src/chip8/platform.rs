@@ -0,0 +1,181 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use super::display::{CHIP8_HEIGHT, CHIP8_WIDTH};
+use super::mem::{MEMORY_SIZE, RESERVED_MEMORY_SIZE};
+use super::quirks::Quirks;
+
+/// Names of the platform presets.
+pub const PLATFORMS: [&str; 8] = [
+    "chip-8",
+    "chip-8-hires",
+    "chip-10",
+    "cosmac-vip",
+    "cosmac-vip-2k",
+    "eti-660",
+    "dream-6800",
+    "chip-48",
+];
+
+// Hi-res programs for the COSMAC VIP start with a jump to 0x260, into a
+// patch of the interpreter that doubles the height of the display. The
+// program itself starts at 0x2C0.
+const HIRES_JUMP: [u8; 2] = [0x12, 0x60];
+const HIRES_START_ADDRESS: usize = 0x2C0;
+
+/// How the machine a program runs on is laid out. Programs written for
+/// a historic computer often only work with its memory map and display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Platform {
+    /// Memory size, in bytes.
+    pub memory_size: usize,
+    /// Where programs are loaded. The memory below is reserved for the
+    /// interpreter and cannot be written to.
+    pub load_address: usize,
+    /// Where programs start running, usually the load address.
+    pub start_address: usize,
+    /// Where the hexadecimal font is kept, in the reserved memory.
+    pub font_address: usize,
+    /// Width and height of the display, in pixels.
+    pub display_size: (usize, usize),
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Platform {
+            memory_size: MEMORY_SIZE,
+            load_address: RESERVED_MEMORY_SIZE,
+            start_address: RESERVED_MEMORY_SIZE,
+            font_address: 0,
+            display_size: (CHIP8_WIDTH, CHIP8_HEIGHT),
+        }
+    }
+}
+
+impl Platform {
+    /// Returns the platform with the given name, together with the quirks
+    /// of its interpreter.
+    pub fn preset(name: &str) -> Option<(Self, Quirks)> {
+        let vip = Quirks {
+            key_release: true,
+            shift: false,
+            load_store: false,
+            jump: false,
+            clip: true,
+            logic: true,
+        };
+        let platform = Platform::default();
+
+        match name {
+            // What the emulator does when no platform is given.
+            "chip-8" => Some((platform, Quirks::default())),
+            "chip-8-hires" => Some((platform.hires(), Quirks::default())),
+            "chip-10" => Some((Platform { display_size: (128, 64), ..platform }, vip)),
+            "cosmac-vip" => Some((platform, vip)),
+            "cosmac-vip-2k" => Some((Platform { memory_size: 2048, ..platform }, vip)),
+            // The ETI-660 keeps its interpreter below 0x600, and has a
+            // taller display.
+            "eti-660" => Some((
+                Platform { load_address: 0x600, start_address: 0x600, display_size: (64, 48), ..platform },
+                vip,
+            )),
+            "dream-6800" => Some((platform, vip)),
+            "chip-48" => Some((
+                platform,
+                Quirks {
+                    key_release: false,
+                    shift: true,
+                    load_store: true,
+                    jump: true,
+                    clip: true,
+                    logic: false,
+                },
+            )),
+            _ => None,
+        }
+    }
+
+    /// The platform `program` runs on. Programs for the 64x32 display
+    /// that start with the hi-res jump run on the 64x64 one instead.
+    pub fn for_program(&self, program: &[u8]) -> Self {
+        let standard = self.display_size == (CHIP8_WIDTH, CHIP8_HEIGHT) && self.start_address == RESERVED_MEMORY_SIZE;
+
+        if standard && program.starts_with(&HIRES_JUMP) {
+            self.hires()
+        } else {
+            *self
+        }
+    }
+
+    /// Tells whether this is the 64x64 hi-res mode of the VIP, where 0230
+    /// clears the display.
+    pub fn is_hires(&self) -> bool {
+        self.display_size == (CHIP8_WIDTH, CHIP8_HEIGHT * 2) && self.start_address == HIRES_START_ADDRESS
+    }
+
+    fn hires(&self) -> Self {
+        Platform {
+            start_address: HIRES_START_ADDRESS,
+            display_size: (CHIP8_WIDTH, CHIP8_HEIGHT * 2),
+            ..*self
+        }
+    }
+
+    /// Size of the largest program that fits in memory.
+    pub fn max_program_size(&self) -> usize {
+        self.memory_size.saturating_sub(self.load_address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Platform, PLATFORMS};
+
+    #[test]
+    fn test_presets() {
+        for name in PLATFORMS.iter() {
+            let (platform, _) = Platform::preset(name).unwrap();
+            assert!(platform.font_address + 80 <= platform.load_address, "{}", name);
+            assert!(platform.load_address < platform.memory_size, "{}", name);
+        }
+
+        assert_eq!(Platform::default(), Platform::preset("chip-8").unwrap().0);
+        assert_eq!(1536, Platform::preset("cosmac-vip-2k").unwrap().0.max_program_size());
+        assert_eq!(0x600, Platform::preset("eti-660").unwrap().0.load_address);
+        assert!(Platform::preset("pdp-11").is_none());
+    }
+
+    #[test]
+    fn test_hires_detection() {
+        let chip_8 = Platform::default();
+        let hires = chip_8.for_program(&[0x12, 0x60, 0x00, 0xE0]);
+        assert_eq!(((64, 64), 0x2C0), (hires.display_size, hires.start_address));
+        assert!(hires.is_hires());
+        assert_eq!(Platform::preset("chip-8-hires").unwrap().0, hires);
+        assert_eq!(hires, hires.for_program(&[0x12, 0x60]));
+
+        assert_eq!(chip_8, chip_8.for_program(&[0x12, 0x62]));
+        let chip_10 = Platform::preset("chip-10").unwrap().0;
+        assert_eq!(chip_10, chip_10.for_program(&[0x12, 0x60]));
+        assert!(!chip_10.is_hires());
+    }
+}
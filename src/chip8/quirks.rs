@@ -0,0 +1,58 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// Behaviours that differ between CHIP-8 interpreters. Programs written
+/// for one of them don't always work properly with the others.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    /// Fx0A waits for the key to be released before storing it, like
+    /// the COSMAC VIP does. Otherwise it returns as soon as a key is
+    /// pressed.
+    pub key_release: bool,
+    /// 8xy6 and 8xyE shift Vx in place, like CHIP-48 and SUPER-CHIP.
+    /// Otherwise they store Vy shifted in Vx, like the COSMAC VIP.
+    pub shift: bool,
+    /// Fx55 and Fx65 leave I unchanged, like CHIP-48 and SUPER-CHIP.
+    /// Otherwise I ends up past the last register, like on the VIP.
+    pub load_store: bool,
+    /// Bxnn jumps to xnn + Vx, like CHIP-48 and SUPER-CHIP. Otherwise
+    /// Bnnn jumps to nnn + V0.
+    pub jump: bool,
+    /// Sprites are cut off at the edges of the display. Otherwise they
+    /// wrap around to the other side.
+    pub clip: bool,
+    /// 8xy1, 8xy2 and 8xy3 reset VF, like on the VIP.
+    pub logic: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            key_release: false,
+            shift: true,
+            load_store: true,
+            jump: false,
+            clip: false,
+            logic: false,
+        }
+    }
+}
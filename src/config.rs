@@ -0,0 +1,539 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::iter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use serde::Deserialize;
+
+use crate::emulator::{Options, Platform, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+use crate::filters::CrtFilter;
+use crate::keymap::Keymap;
+use crate::palette::{Palette, Rgb};
+
+/// Speeds the emulator can run at. Frames would last for hours below it,
+/// and too short to time above it.
+const SPEEDS: RangeInclusive<f32> = 0.01..=100.0;
+
+/// Settings read from a TOML file. Everything is optional: whatever is
+/// left out keeps its current value when the configuration is applied.
+///
+/// ```toml
+/// [video]
+/// scale = 8
+/// theme = "amber"
+///
+/// [audio]
+/// frequency = 440.0
+///
+/// [keys]
+/// 5 = ["W", "Up"]
+///
+/// # Only used when running PONG.
+/// [roms.PONG]
+/// keymap = "pong"
+///
+/// [roms.PONG.keys]
+/// 1 = "Left Shift"
+/// 4 = "Left Ctrl"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Built-in keymap to start from. Only the one of the last layer that
+    /// names one is used, and the `keys` of every layer go on top of it.
+    pub keymap: Option<String>,
+    pub video: VideoConfig,
+    pub input: InputConfig,
+    pub audio: AudioConfig,
+    pub emulation: EmulationConfig,
+    pub quirks: QuirksConfig,
+    /// Host keys bound to each button of the CHIP-8 keypad, which is
+    /// written as a hexadecimal digit. These replace the keys the button
+    /// has in the keymap.
+    pub keys: BTreeMap<String, Keys>,
+    /// Settings that only apply to one ROM, keyed by its file name.
+    pub roms: BTreeMap<String, Config>,
+}
+
+/// One key name, or a list of them.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct VideoConfig {
+    pub scale: Option<usize>,
+    pub integer_scaling: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub theme: Option<String>,
+    /// All four colours: background, first plane, second plane and
+    /// both planes.
+    pub colors: Option<Vec<String>>,
+    pub background: Option<String>,
+    pub foreground: Option<String>,
+    pub deflicker: Option<String>,
+    /// Comma separated list of CRT filters.
+    pub filters: Option<String>,
+    pub keypad_overlay: Option<bool>,
+    pub stats_overlay: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct InputConfig {
+    /// How far an analog stick has to be pushed before it presses a
+    /// keypad button, from 0 to 1.
+    pub dead_zone: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AudioConfig {
+    /// Frequency of the buzzer, in Hz.
+    pub frequency: Option<f32>,
+    pub waveform: Option<String>,
+    /// From 0 to 1.
+    pub volume: Option<f32>,
+    pub muted: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EmulationConfig {
+    /// Number of instructions executed per frame, at 60 frames per
+    /// second.
+    pub instructions_per_frame: Option<usize>,
+    /// How fast the emulator runs, 1 being 60 frames per second.
+    pub speed: Option<f32>,
+    /// Speed of fast-forward, or 0 to run as fast as possible.
+    pub fast_forward: Option<f32>,
+    /// Speed of slow motion.
+    pub slow_motion: Option<f32>,
+    /// Gives programs 64 KiB of memory, like XO-CHIP.
+    pub xo_chip: Option<bool>,
+    /// Platform preset, which sets the memory, the load address, the
+    /// display size and the quirks.
+    pub platform: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QuirksConfig {
+    /// Fx0A waits for the key to be released, like on the COSMAC VIP.
+    pub key_release: Option<bool>,
+    /// 8xy6 and 8xyE shift Vx in place instead of Vy.
+    pub shift: Option<bool>,
+    /// Fx55 and Fx65 leave I unchanged.
+    pub load_store: Option<bool>,
+    /// Bxnn jumps to xnn + Vx instead of nnn + V0.
+    pub jump: Option<bool>,
+    /// Sprites are cut off at the edges of the display.
+    pub clip: Option<bool>,
+    /// 8xy1, 8xy2 and 8xy3 reset VF.
+    pub logic: Option<bool>,
+}
+
+impl Config {
+    /// Location of the global configuration file:
+    /// `$XDG_CONFIG_HOME/chippy/config.toml`, or
+    /// `~/.config/chippy/config.toml` if that variable is not set.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("chippy").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        toml::from_str(&contents).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Overwrites the options with the settings from this configuration,
+    /// followed by the ones specific to `rom`, if there are any.
+    pub fn apply(&self, options: &mut Options, rom: Option<&str>) -> Result<(), String> {
+        self.apply_with(options, rom, &[], &Config::default())
+    }
+
+    /// Like `apply`, with `rom_settings`, which come with the ROM itself,
+    /// going in order between the global settings and the section of the
+    /// ROM, and `flags` from the command line going over everything.
+    pub fn apply_with(
+        &self,
+        options: &mut Options,
+        rom: Option<&str>,
+        rom_settings: &[Config],
+        flags: &Config,
+    ) -> Result<(), String> {
+        let layers: Vec<&Config> = iter::once(self)
+            .chain(rom_settings)
+            .chain(rom.and_then(|name| self.roms.get(name)))
+            .chain(iter::once(flags))
+            .collect();
+
+        // The platform goes first, whichever layer picks it, so that the
+        // quirks given anywhere go over the ones of the platform.
+        if let Some(name) = layers.iter().rev().find_map(|c| c.emulation.platform.as_ref()) {
+            let (platform, quirks) = Platform::preset(name).ok_or(format!("unknown platform: {}", name))?;
+            options.platform = platform;
+            options.quirks = quirks;
+            options.detect_hires = false;
+        }
+
+        // So does the keymap, so that picking one with --keymap or for a
+        // ROM keeps the keys bound anywhere else.
+        if let Some(name) = layers.iter().rev().find_map(|c| c.keymap.as_ref()) {
+            options.keys = Keymap::preset(name).ok_or(format!("unknown keymap: {}", name))?;
+        }
+
+        layers.iter().try_for_each(|config| config.apply_settings(options))
+    }
+
+    fn apply_settings(&self, options: &mut Options) -> Result<(), String> {
+        let video = &self.video;
+
+        if let Some(scale) = video.scale {
+            if scale == 0 {
+                return Err("invalid scale: 0".to_string());
+            }
+            options.scale = scale;
+        }
+
+        if let Some(integer_scaling) = video.integer_scaling {
+            options.integer_scaling = integer_scaling;
+        }
+
+        if let Some(fullscreen) = video.fullscreen {
+            options.fullscreen = fullscreen;
+        }
+
+        if let Some(theme) = &video.theme {
+            options.palette = Palette::theme(theme).ok_or(format!("unknown theme: {}", theme))?;
+        }
+
+        if let Some(colors) = &video.colors {
+            let colors = colors
+                .iter()
+                .map(|c| c.trim().parse())
+                .collect::<Result<Vec<Rgb>, _>>()?;
+
+            if colors.len() != 4 {
+                return Err("exactly four colours are needed".to_string());
+            }
+
+            options.palette.colors.copy_from_slice(&colors);
+        }
+
+        if let Some(background) = &video.background {
+            options.palette.colors[0] = background.parse()?;
+        }
+
+        if let Some(foreground) = &video.foreground {
+            options.palette.colors[1] = foreground.parse()?;
+        }
+
+        if let Some(deflicker) = &video.deflicker {
+            options.deflicker = deflicker.parse()?;
+        }
+
+        if let Some(filters) = &video.filters {
+            options.crt = CrtFilter::parse_chain(filters)?;
+        }
+
+        if let Some(dead_zone) = self.input.dead_zone {
+            if !(0.0..=1.0).contains(&dead_zone) {
+                return Err(format!("invalid dead zone: {}", dead_zone));
+            }
+            options.dead_zone = dead_zone;
+        }
+
+        if let Some(keypad_overlay) = video.keypad_overlay {
+            options.keypad_overlay = keypad_overlay;
+        }
+
+        if let Some(stats_overlay) = video.stats_overlay {
+            options.stats_overlay = stats_overlay;
+        }
+
+        if let Some(frequency) = self.audio.frequency {
+            if !frequency.is_finite() || frequency <= 0.0 {
+                return Err(format!("invalid frequency: {}", frequency));
+            }
+            options.tone.frequency = frequency;
+        }
+
+        if let Some(waveform) = &self.audio.waveform {
+            options.tone.waveform = waveform.parse()?;
+        }
+
+        if let Some(volume) = self.audio.volume {
+            if !(0.0..=1.0).contains(&volume) {
+                return Err(format!("invalid volume: {}", volume));
+            }
+            options.tone.volume = volume;
+        }
+
+        if let Some(muted) = self.audio.muted {
+            options.muted = muted;
+        }
+
+        if let Some(instructions) = self.emulation.instructions_per_frame {
+            if instructions == 0 {
+                return Err("invalid number of instructions per frame: 0".to_string());
+            }
+            options.instructions_per_frame = instructions;
+        }
+
+        let speed = |name: &str, value: f32, allow_zero: bool| {
+            if SPEEDS.contains(&value) || (allow_zero && value == 0.0) {
+                Ok(value)
+            } else {
+                Err(format!(
+                    "invalid {}: {} (it must be between {} and {})",
+                    name,
+                    value,
+                    SPEEDS.start(),
+                    SPEEDS.end()
+                ))
+            }
+        };
+
+        if let Some(s) = self.emulation.speed {
+            options.speed = speed("speed", s, false)?;
+        }
+
+        if let Some(s) = self.emulation.fast_forward {
+            options.fast_forward = speed("fast-forward speed", s, true)?;
+        }
+
+        if let Some(s) = self.emulation.slow_motion {
+            options.slow_motion = speed("slow motion speed", s, false)?;
+        }
+
+        match self.emulation.xo_chip {
+            Some(true) => options.platform.memory_size = XO_CHIP_MEMORY_SIZE,
+            Some(false) if options.platform.memory_size == XO_CHIP_MEMORY_SIZE => {
+                options.platform.memory_size = MEMORY_SIZE
+            }
+            _ => {}
+        }
+
+        let quirks = &self.quirks;
+        let settings = [
+            (quirks.key_release, &mut options.quirks.key_release),
+            (quirks.shift, &mut options.quirks.shift),
+            (quirks.load_store, &mut options.quirks.load_store),
+            (quirks.jump, &mut options.quirks.jump),
+            (quirks.clip, &mut options.quirks.clip),
+            (quirks.logic, &mut options.quirks.logic),
+        ];
+        for (setting, quirk) in settings {
+            if let Some(value) = setting {
+                *quirk = value;
+            }
+        }
+
+        for (button, keys) in &self.keys {
+            let button = match u8::from_str_radix(button, 16) {
+                Ok(b) if button.len() == 1 => b,
+                _ => return Err(format!("invalid keypad button: {}", button)),
+            };
+
+            match keys {
+                Keys::One(key) => options.keys.bind(button, slice::from_ref(key)),
+                Keys::Many(keys) => options.keys.bind(button, keys),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, InputConfig};
+    use crate::emulator::{Options, XO_CHIP_MEMORY_SIZE};
+    use crate::palette::{Palette, Rgb};
+    use crate::sound::Waveform;
+
+    const CONFIG: &str = r##"
+        [video]
+        scale = 4
+        theme = "octo"
+        foreground = "#123456"
+
+        [input]
+        dead-zone = 0.5
+
+        [audio]
+        frequency = 440.0
+        waveform = "sine"
+
+        [emulation]
+        instructions-per-frame = 20
+
+        [keys]
+        5 = "Up"
+        a = ["Space", "Return"]
+
+        [roms.PONG]
+        keymap = "pong"
+
+        [roms.PONG.video]
+        theme = "classic"
+
+        [roms.PONG.keys]
+        1 = "Left Shift"
+    "##;
+
+    #[test]
+    fn test_apply_config() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let mut options = Options::default();
+        config.apply(&mut options, Some("BRIX")).unwrap();
+
+        assert_eq!(4, options.scale);
+        assert_eq!(Rgb(0x99, 0x66, 0x00), options.palette.colors[0]);
+        assert_eq!(Rgb(0x12, 0x34, 0x56), options.palette.colors[1]);
+        assert_eq!(0.5, options.dead_zone);
+        assert_eq!(440.0, options.tone.frequency);
+        assert_eq!(Waveform::Sine, options.tone.waveform);
+        assert_eq!(20, options.instructions_per_frame);
+        assert_eq!(["Up"], options.keys.keys(0x5));
+        assert_eq!(["Space", "Return"], options.keys.keys(0xA));
+        assert_eq!(["1"], options.keys.keys(0x1));
+    }
+
+    #[test]
+    fn test_apply_rom_overrides() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let mut options = Options::default();
+        config.apply(&mut options, Some("PONG")).unwrap();
+
+        assert_eq!(4, options.scale);
+        assert_eq!(Palette::theme("classic").unwrap(), options.palette);
+        // The keys of both sections go on top of the keymap of the ROM.
+        assert_eq!(["Left Shift"], options.keys.keys(0x1));
+        assert_eq!(["Up"], options.keys.keys(0x5));
+        assert_eq!(["4", "Pad2 dpup", "Pad2 -lefty"], options.keys.keys(0xC));
+
+        // So do they with a keymap from the command line.
+        let flags = Config { keymap: Some("arrows".to_string()), ..Config::default() };
+        let mut options = Options::default();
+        config.apply_with(&mut options, Some("BRIX"), &[], &flags).unwrap();
+        assert_eq!(["Space", "Return"], options.keys.keys(0xA));
+        assert_eq!(["Up"], options.keys.keys(0x5));
+        assert_eq!(["A", "Left", "Pad1 dpleft", "Pad1 -leftx"], options.keys.keys(0x7));
+    }
+
+    #[test]
+    fn test_apply_rom_settings() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let rom_settings: Config = toml::from_str(r#"
+            video.theme = "amber"
+            emulation.instructions-per-frame = 15
+            quirks.clip = true
+        "#).unwrap();
+
+        let mut options = Options::default();
+        config.apply_with(&mut options, Some("PONG"), &[rom_settings], &Config::default()).unwrap();
+
+        // The settings of the ROM go over the global ones, but the user's
+        // section for the ROM goes over them.
+        assert_eq!(15, options.instructions_per_frame);
+        assert!(options.quirks.clip);
+        assert_eq!(Palette::theme("classic").unwrap(), options.palette);
+    }
+
+    #[test]
+    fn test_dead_zone() {
+        let mut options = Options::default();
+        let config = |dead_zone: f32| Config { input: InputConfig { dead_zone: Some(dead_zone) }, ..Config::default() };
+
+        assert!(config(1.0).apply(&mut options, None).is_ok());
+        assert_eq!(1.0, options.dead_zone);
+        assert!(config(1.5).apply(&mut options, None).is_err());
+        assert!(config(-0.1).apply(&mut options, None).is_err());
+    }
+
+    #[test]
+    fn test_apply_platform() {
+        let config: Config = toml::from_str(r#"
+            quirks.shift = true
+            emulation.xo-chip = true
+        "#).unwrap();
+        let rom_settings: Config = toml::from_str(r#"emulation.platform = "eti-660""#).unwrap();
+
+        // The platform sets the quirks, but the ones given anywhere else
+        // still win.
+        let mut options = Options::default();
+        config.apply_with(&mut options, None, &[rom_settings], &Config::default()).unwrap();
+        assert_eq!(0x600, options.platform.load_address);
+        assert_eq!(XO_CHIP_MEMORY_SIZE, options.platform.memory_size);
+        assert!(options.quirks.shift);
+        assert!(options.quirks.clip);
+
+        // The same goes for a platform picked on the command line.
+        let config: Config = toml::from_str("quirks.clip = false").unwrap();
+        let flags: Config = toml::from_str(r#"emulation.platform = "cosmac-vip""#).unwrap();
+        let mut options = Options::default();
+        config.apply_with(&mut options, None, &[], &flags).unwrap();
+        assert!(options.quirks.logic);
+        assert!(!options.quirks.clip);
+
+        let config: Config = toml::from_str(r#"emulation.platform = "apple-ii""#).unwrap();
+        assert!(config.apply(&mut options, None).is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(toml::from_str::<Config>("[video]\nzoom = 2").is_err());
+        assert!(toml::from_str::<Config>("[emulation]\ncycle-delay-ms = 2").is_err());
+
+        let mut options = Options::default();
+        let config: Config = toml::from_str("[keys]\n10 = \"Q\"").unwrap();
+        assert!(config.apply(&mut options, None).is_err());
+
+        let config: Config = toml::from_str("[video]\ntheme = \"nope\"").unwrap();
+        assert!(config.apply(&mut options, None).is_err());
+
+        for speed in ["1e-30", "0.0", "-1.0", "1000.0", "nan"] {
+            let config: Config = toml::from_str(&format!("[emulation]\nspeed = {}", speed)).unwrap();
+            assert!(config.apply(&mut options, None).is_err(), "{}", speed);
+        }
+        let config: Config = toml::from_str("[emulation]\nspeed = 0.01\nfast-forward = 0.0").unwrap();
+        assert!(config.apply(&mut options, None).is_ok());
+    }
+}
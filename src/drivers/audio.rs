@@ -20,14 +20,24 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 
+use crate::sound::{Playback, Synth, Timeline, Tone};
+
 pub struct AudioDriver {
-    device: AudioDevice<SquareWave>
+    // The device keeps playing all the time, silence included, so that
+    // the buzzer can fade in and out instead of popping.
+    device: AudioDevice<Buzzer>,
+    sample_rate: u32,
+    timeline: Arc<Timeline>,
+    muted: Arc<AtomicBool>,
 }
 
 impl AudioDriver {
-    pub fn new(ctx: &sdl2::Sdl) -> Self {
+    pub fn new(ctx: &sdl2::Sdl, tone: Tone) -> Self {
         let audio_subsystem = ctx.audio().unwrap();
 
         let desired_spec = AudioSpecDesired {
@@ -36,40 +46,57 @@ impl AudioDriver {
             samples: None,     // default sample size
         };
 
+        let timeline = Arc::new(Timeline::new());
+        let muted = Arc::new(AtomicBool::new(false));
+
         let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            SquareWave {
-                phase_inc: 240.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 1.25,
+            Buzzer {
+                synth: Synth::new(tone, spec.freq as u32),
+                playback: Playback::new(spec.freq as u32),
+                timeline: Arc::clone(&timeline),
+                muted: Arc::clone(&muted),
             }
         }).unwrap();
+        device.resume();
+        let sample_rate = device.spec().freq as u32;
 
-        AudioDriver { device }
+        AudioDriver { device, sample_rate, timeline, muted }
     }
 
-    pub fn start_beeping(&self) {
-        self.device.resume();
+    /// Switches to another tone, e.g. when another ROM is loaded.
+    pub fn set_tone(&mut self, tone: Tone) {
+        self.device.lock().synth = Synth::new(tone, self.sample_rate);
     }
 
-    pub fn stop_beeping(&self) {
-        self.device.pause();
+    /// Must be called at the end of every emulated frame, with whether the
+    /// buzzer was on during that frame.
+    pub fn end_frame(&self, beeping: bool) {
+        self.timeline.push(beeping);
+    }
+
+    /// Silences the buzzer, or lets it be heard again. Returns whether it
+    /// is muted now.
+    pub fn toggle_mute(&self) -> bool {
+        !self.muted.fetch_xor(true, Ordering::Relaxed)
     }
 }
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+struct Buzzer {
+    synth: Synth,
+    playback: Playback,
+    timeline: Arc<Timeline>,
+    muted: Arc<AtomicBool>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Buzzer {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
+        let muted = self.muted.load(Ordering::Relaxed);
+
         for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 { self.volume } else { -self.volume };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+            let on = self.playback.next(&self.timeline) && !muted;
+            *x = self.synth.next(on);
         }
     }
 }
@@ -0,0 +1,423 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::time::Instant;
+
+use sdl2::{EventPump, GameControllerSubsystem};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::mouse::MouseButton;
+
+use crate::emulator::Options;
+use crate::input::{InputQueue, KeyEvent};
+
+/// Requests for the emulator itself, rather than for the CHIP-8
+/// keypad, which come from hotkeys or from the window.
+pub enum Command {
+    /// F2 - opens the launcher.
+    OpenLauncher,
+    /// Keys used to move around the launcher, while it is open.
+    Menu(MenuKey),
+    /// F1 - shows or hides the stats panel.
+    ToggleStats,
+    /// F3 - pauses or resumes the emulation.
+    TogglePause,
+    /// F4 - runs a single frame, pausing the emulation first.
+    FrameAdvance,
+    /// F5 - restarts the program.
+    Reset,
+    /// F6 - reads the ROM file again and restarts the program.
+    Reload,
+    /// F7 - switches between normal speed and slow motion.
+    ToggleSlowMotion,
+    /// F8 - switches between normal speed and fast-forward.
+    ToggleFastForward,
+    /// Page Up - executes one more instruction per frame.
+    MoreInstructions,
+    /// Page Down - executes one instruction less per frame.
+    FewerInstructions,
+    /// F9 - mutes or unmutes the buzzer.
+    ToggleMute,
+    /// F10 - shows or hides the on-screen keypad.
+    ToggleKeypad,
+    /// F11 - switches between windowed and fullscreen mode.
+    ToggleFullscreen,
+    /// F12 - saves the current screen to a PNG file.
+    Screenshot,
+    /// The window was resized or uncovered and has to be drawn again.
+    Redraw,
+}
+
+/// Something on the host that can be bound to a keypad button. Controller
+/// inputs belong to a player: the first controller to be plugged in is
+/// player 1, the second one player 2, and so on.
+#[derive(Clone, Copy, PartialEq)]
+enum Input {
+    Key(Scancode),
+    Button(usize, Button),
+    /// Player, axis and whether the stick has to be pushed towards the
+    /// positive end of the axis.
+    Axis(usize, Axis, bool),
+}
+
+impl Input {
+    /// Parses a keymap entry. Controller inputs look like `Pad1 a`,
+    /// `Pad1 dpup` or `Pad2 -lefty`, using the names SDL uses in
+    /// controller mappings, and anything else is a key.
+    fn parse(name: &str) -> Result<Self, String> {
+        let pad = name
+            .strip_prefix("Pad")
+            .and_then(|rest| rest.split_once(' '))
+            .and_then(|(player, input)| Some((player.parse::<usize>().ok()?, input)));
+
+        let input = match pad {
+            Some((player, input)) if player > 0 => {
+                if let Some(button) = Button::from_string(input) {
+                    Some(Input::Button(player - 1, button))
+                } else {
+                    let axis = match (input.strip_prefix('+'), input.strip_prefix('-')) {
+                        (Some(axis), _) => Some((axis, true)),
+                        (_, Some(axis)) => Some((axis, false)),
+                        _ => None,
+                    };
+                    axis.and_then(|(axis, positive)| {
+                        Axis::from_string(axis).map(|axis| Input::Axis(player - 1, axis, positive))
+                    })
+                }
+            }
+            _ => Scancode::from_name(name).map(Input::Key),
+        };
+
+        input.ok_or(format!("unknown key: {}", name))
+    }
+}
+
+/// Keys that navigate the launcher.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Select,
+    Back,
+}
+
+pub struct InputDriver {
+    event_pump: EventPump,
+    controller_subsystem: GameControllerSubsystem,
+    // Open controllers, indexed by player. Unplugging a controller leaves
+    // a gap that the next one to be plugged in takes.
+    controllers: Vec<Option<GameController>>,
+    // Keypad button bound to each input.
+    bindings: Vec<(Input, usize)>,
+    // Whether the input of each binding is held down.
+    held: Vec<bool>,
+    // Button of the on-screen keypad held down with the mouse.
+    clicked: Option<usize>,
+    // State of the keypad as last sent to the input queue.
+    keypad: [bool; 16],
+    // How far a stick has to be pushed before it counts, out of i16::MAX.
+    dead_zone: i16,
+    // Whether the launcher is open, in which case the navigation keys
+    // are turned into menu commands instead.
+    menu: bool,
+}
+
+impl InputDriver {
+    pub fn new(ctx: &sdl2::Sdl, options: &Options) -> Result<Self, String> {
+        let bindings = InputDriver::bindings(options)?;
+
+        // Controllers that are already plugged in get a ControllerDeviceAdded
+        // event as well, so they are all opened in read().
+        Ok(InputDriver {
+            event_pump: ctx.event_pump()?,
+            controller_subsystem: ctx.game_controller()?,
+            controllers: Vec::new(),
+            held: vec![false; bindings.len()],
+            bindings,
+            clicked: None,
+            keypad: [false; 16],
+            dead_zone: InputDriver::dead_zone(options),
+            menu: false,
+        })
+    }
+
+    /// Switches to the key bindings and dead zone of `options`, e.g. when
+    /// another ROM is loaded.
+    pub fn set_keys(&mut self, options: &Options) -> Result<(), String> {
+        self.bindings = InputDriver::bindings(options)?;
+        self.held = vec![false; self.bindings.len()];
+        self.dead_zone = InputDriver::dead_zone(options);
+        Ok(())
+    }
+
+    fn bindings(options: &Options) -> Result<Vec<(Input, usize)>, String> {
+        let mut bindings = Vec::new();
+
+        for button in 0..16 {
+            for name in options.keys.keys(button) {
+                bindings.push((Input::parse(name)?, button as usize));
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    fn dead_zone(options: &Options) -> i16 {
+        (options.dead_zone.clamp(0.0, 1.0) * i16::MAX as f32) as i16
+    }
+
+    /// Switches the navigation keys between the launcher and the keypad.
+    pub fn set_menu(&mut self, menu: bool) {
+        self.menu = menu;
+    }
+
+    /// Handles the pending window events. Keypad buttons that go down or
+    /// up are pushed to `queue`, and `keypad_at` tells which button of the
+    /// on-screen keypad, if any, is at some window coordinates.
+    pub fn read<F>(&mut self, queue: &mut InputQueue, keypad_at: F) -> Result<Vec<Command>, &str>
+    where
+        F: Fn(i32, i32) -> Option<usize>,
+    {
+        let mut commands = Vec::new();
+        let events: Vec<Event> = self.event_pump.poll_iter().collect();
+
+        for event in events {
+            if let Event::KeyDown { keycode: Some(keycode), .. } = event {
+                if let Some(key) = self.menu_key(keycode) {
+                    commands.push(Command::Menu(key));
+                    continue;
+                }
+            }
+
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return Err("Finito"),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F1),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleStats),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::OpenLauncher),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F3),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::TogglePause),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F4),
+                    ..
+                } => commands.push(Command::FrameAdvance),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::Reset),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F6),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::Reload),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleSlowMotion),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleFastForward),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageUp),
+                    ..
+                } => commands.push(Command::MoreInstructions),
+                Event::KeyDown {
+                    keycode: Some(Keycode::PageDown),
+                    ..
+                } => commands.push(Command::FewerInstructions),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleMute),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleKeypad),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::ToggleFullscreen),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => commands.push(Command::Screenshot),
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => commands.push(Command::Redraw),
+                Event::KeyDown { scancode: Some(key), repeat: false, .. } => {
+                    self.set_held(|input| *input == Input::Key(key), true);
+                }
+                Event::KeyUp { scancode: Some(key), .. } => {
+                    self.set_held(|input| *input == Input::Key(key), false);
+                }
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    self.clicked = keypad_at(x, y);
+                }
+                Event::MouseMotion { mousestate, x, y, .. } if mousestate.left() => {
+                    self.clicked = keypad_at(x, y);
+                }
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => self.clicked = None,
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(player) = self.player(which) {
+                        self.set_held(|input| *input == Input::Button(player, button), true);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(player) = self.player(which) {
+                        self.set_held(|input| *input == Input::Button(player, button), false);
+                    }
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    if let Some(player) = self.player(which) {
+                        let dead_zone = self.dead_zone;
+                        self.set_held(|input| *input == Input::Axis(player, axis, true), value > dead_zone);
+                        self.set_held(|input| *input == Input::Axis(player, axis, false), value < -dead_zone);
+                    }
+                }
+                Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => self.remove_controller(which),
+                _ => {}
+            }
+
+            self.update(queue);
+        }
+
+        Ok(commands)
+    }
+
+    fn menu_key(&self, keycode: Keycode) -> Option<MenuKey> {
+        if !self.menu {
+            return None;
+        }
+
+        match keycode {
+            Keycode::Up => Some(MenuKey::Up),
+            Keycode::Down => Some(MenuKey::Down),
+            Keycode::PageUp => Some(MenuKey::PageUp),
+            Keycode::PageDown => Some(MenuKey::PageDown),
+            Keycode::Return | Keycode::KpEnter => Some(MenuKey::Select),
+            Keycode::Escape => Some(MenuKey::Back),
+            _ => None,
+        }
+    }
+
+    fn set_held<P: Fn(&Input) -> bool>(&mut self, matches: P, held: bool) {
+        for (i, (input, _)) in self.bindings.iter().enumerate() {
+            if matches(input) {
+                self.held[i] = held;
+            }
+        }
+    }
+
+    /// Pushes an event for every keypad button that changed since the
+    /// last call.
+    fn update(&mut self, queue: &mut InputQueue) {
+        let mut keypad = [false; 16];
+
+        for (&(_, button), &held) in self.bindings.iter().zip(&self.held) {
+            keypad[button] |= held;
+        }
+        if let Some(button) = self.clicked {
+            keypad[button] = true;
+        }
+
+        let time = Instant::now();
+        for (button, (&pressed, &was_pressed)) in keypad.iter().zip(&self.keypad).enumerate() {
+            if pressed != was_pressed {
+                queue.push(KeyEvent { time, button, pressed });
+            }
+        }
+
+        self.keypad = keypad;
+    }
+
+    /// Returns the player using the controller with the given instance id.
+    fn player(&self, instance_id: u32) -> Option<usize> {
+        self.controllers
+            .iter()
+            .position(|c| c.as_ref().map(|c| c.instance_id()) == Some(instance_id))
+    }
+
+    fn add_controller(&mut self, joystick_index: u32) {
+        let controller = match self.controller_subsystem.open(joystick_index) {
+            Ok(controller) => controller,
+            Err(e) => {
+                eprintln!("Could not open game controller: {}", e);
+                return;
+            }
+        };
+
+        let id = controller.instance_id();
+        if self.controllers.iter().flatten().any(|c| c.instance_id() == id) {
+            return;
+        }
+
+        let name = controller.name();
+        let player = match self.controllers.iter().position(Option::is_none) {
+            Some(i) => {
+                self.controllers[i] = Some(controller);
+                i
+            }
+            None => {
+                self.controllers.push(Some(controller));
+                self.controllers.len() - 1
+            }
+        };
+
+        eprintln!("{} connected as player {}", name, player + 1);
+    }
+
+    fn remove_controller(&mut self, instance_id: u32) {
+        if let Some(player) = self.player(instance_id) {
+            self.controllers[player] = None;
+            self.set_held(|input| match *input {
+                Input::Button(p, _) | Input::Axis(p, _, _) => p == player,
+                Input::Key(_) => false,
+            }, false);
+        }
+    }
+}
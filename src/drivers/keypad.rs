@@ -0,0 +1,116 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::drivers::osd;
+use crate::palette::{Palette, Rgb};
+
+// Keypad buttons in the order they appear on the keypad, row by row.
+const LAYOUT: [usize; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+// Side of the overlay, as a fraction of the shortest side of the window.
+const SIZE: f32 = 0.4;
+// Space around each key, as a fraction of the size of a key.
+const GAP: f32 = 0.1;
+const ALPHA: u8 = 200;
+
+/// Returns where each keypad button is drawn, indexed by button. The
+/// overlay sits in the bottom right corner of the window.
+pub(crate) fn layout(window: (u32, u32)) -> [Rect; 16] {
+    let side = (window.0.min(window.1) as f32 * SIZE) as i32;
+    let key = side / 4;
+    let gap = ((key as f32 * GAP) as i32).max(1);
+    let left = window.0 as i32 - key * 4;
+    let top = window.1 as i32 - key * 4;
+
+    let mut rects = [Rect::new(0, 0, 1, 1); 16];
+    for (i, &button) in LAYOUT.iter().enumerate() {
+        let x = left + (i % 4) as i32 * key;
+        let y = top + (i / 4) as i32 * key;
+        let side = (key - 2 * gap).max(1) as u32;
+        rects[button] = Rect::new(x + gap, y + gap, side, side);
+    }
+
+    rects
+}
+
+/// Returns the keypad button under the window coordinates `(x, y)`.
+pub(crate) fn button_at(window: (u32, u32), x: i32, y: i32) -> Option<usize> {
+    layout(window).iter().position(|r| r.contains_point((x, y)))
+}
+
+/// Draws the keypad on top of whatever is on the canvas. Pressed buttons
+/// are filled with the foreground colour.
+pub(crate) fn draw(canvas: &mut Canvas<Window>, palette: &Palette, keypad: &[bool; 16]) {
+    let window = canvas.output_size().expect("could not get window size");
+    let color = |c: Rgb, alpha: u8| Color::RGBA(c.0, c.1, c.2, alpha);
+    let (bg, fg) = (palette.background(), palette.foreground());
+
+    canvas.set_blend_mode(BlendMode::Blend);
+
+    for (button, rect) in layout(window).iter().enumerate() {
+        let (face, ink) = if keypad[button] { (fg, bg) } else { (bg, fg) };
+
+        canvas.set_draw_color(color(face, ALPHA));
+        let _ = canvas.fill_rect(*rect);
+        canvas.set_draw_color(color(fg, ALPHA));
+        let _ = canvas.draw_rect(*rect);
+
+        // The 4x5 font sprite of the button, centered on the key.
+        let pixel = (rect.width() / 8).max(1);
+        let x = rect.x() + (rect.width() as i32 - 4 * pixel as i32) / 2;
+        let y = rect.y() + (rect.height() as i32 - 5 * pixel as i32) / 2;
+
+        canvas.set_draw_color(color(ink, 255));
+        osd::draw_text(canvas, x, y, pixel, &format!("{:X}", button));
+    }
+
+    canvas.set_blend_mode(BlendMode::None);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{button_at, layout};
+
+    #[test]
+    fn test_keypad_layout() {
+        let rects = layout((640, 320));
+        // 128x128 pixels in the bottom right corner, 32 pixels per key.
+        assert_eq!((515, 195), (rects[0x1].x(), rects[0x1].y()));
+        assert_eq!((611, 291), (rects[0xF].x(), rects[0xF].y()));
+        assert_eq!(26, rects[0x0].width());
+
+        assert_eq!(Some(0x1), button_at((640, 320), 520, 200));
+        assert_eq!(Some(0x0), button_at((640, 320), 560, 300));
+        assert_eq!(None, button_at((640, 320), 512, 192));
+        assert_eq!(None, button_at((640, 320), 100, 100));
+    }
+}
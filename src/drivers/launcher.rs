@@ -0,0 +1,158 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::drivers::osd::{self, ADVANCE, LINE_HEIGHT};
+use crate::launcher::Launcher;
+use crate::palette::{Palette, Rgb};
+
+const TITLE: &str = "Select a ROM";
+const HINTS: &str = "Up/Down: select  Enter: start  Esc: back";
+// Width of the list of ROMs, as a fraction of the width of the window.
+const LIST_WIDTH: f32 = 0.35;
+// Space around everything, in font pixels.
+const MARGIN: u32 = 4;
+
+/// Where the parts of the launcher go in a window of the given size:
+/// the list of ROMs, the preview and the details below it.
+pub(crate) fn layout(window: (u32, u32)) -> (Rect, Rect, Rect) {
+    let pixel = osd::pixel_size(window);
+    let margin = (MARGIN * pixel) as i32;
+    let line = LINE_HEIGHT as i32 * pixel as i32;
+    let (width, height) = (window.0 as i32, window.1 as i32);
+
+    // The title goes above everything else, and the hints below.
+    let top = margin + 2 * line;
+    let bottom = height - margin - 2 * line;
+    let list_width = (width as f32 * LIST_WIDTH) as i32;
+    let list = Rect::new(margin, top, list_width.max(1) as u32, (bottom - top).max(1) as u32);
+
+    // The preview keeps the 2:1 shape of the display.
+    let left = margin * 2 + list_width;
+    let preview_width = (width - left - margin).max(2);
+    let preview_height = preview_width.min((bottom - top) * 2 / 3 * 2) / 2;
+    let preview = Rect::new(left, top, (preview_height * 2) as u32, preview_height.max(1) as u32);
+    let details_top = top + preview_height + margin;
+    let details = Rect::new(left, details_top, preview_width as u32, (bottom - details_top).max(1) as u32);
+
+    (list, preview, details)
+}
+
+/// Draws the launcher over the whole window.
+pub(crate) fn draw(canvas: &mut Canvas<Window>, palette: &Palette, launcher: &Launcher) {
+    let window = canvas.output_size().expect("could not get window size");
+    let pixel = osd::pixel_size(window);
+    let margin = (MARGIN * pixel) as i32;
+    let line = LINE_HEIGHT as i32 * pixel as i32;
+    let color = |c: Rgb| Color::RGB(c.0, c.1, c.2);
+    let (bg, fg) = (palette.background(), palette.foreground());
+    let (list, preview, details) = layout(window);
+
+    canvas.set_draw_color(color(bg));
+    canvas.clear();
+
+    canvas.set_draw_color(color(fg));
+    osd::draw_text(canvas, margin, margin, pixel, TITLE);
+    osd::draw_text(canvas, margin, window.1 as i32 - margin - line, pixel, HINTS);
+
+    // Only the entries that fit are shown, keeping the selected one in
+    // the middle where possible.
+    let entries = launcher.entries();
+    let rows = (list.height() as i32 / line).max(1) as usize;
+    let first = launcher
+        .selected_index()
+        .saturating_sub(rows / 2)
+        .min(entries.len().saturating_sub(rows));
+    let chars = (list.width() / (ADVANCE as u32 * pixel)).max(1) as usize;
+
+    for (i, entry) in entries.iter().enumerate().skip(first).take(rows) {
+        let y = list.y() + (i - first) as i32 * line;
+        let name: String = entry.name.chars().take(chars).collect();
+
+        if i == launcher.selected_index() {
+            canvas.set_draw_color(color(fg));
+            let _ = canvas.fill_rect(Rect::new(list.x() - pixel as i32, y - pixel as i32, list.width(), line as u32));
+            canvas.set_draw_color(color(bg));
+        } else {
+            canvas.set_draw_color(color(fg));
+        }
+        osd::draw_text(canvas, list.x(), y, pixel, &name);
+    }
+
+    canvas.set_draw_color(color(fg));
+    let _ = canvas.draw_rect(preview);
+
+    match launcher.preview() {
+        Some(display) => {
+            let scale = (preview.width() as usize / display.width()).max(1) as u32;
+            let x = preview.x() + (preview.width() as i32 - (display.width() as u32 * scale) as i32) / 2;
+            let y = preview.y() + (preview.height() as i32 - (display.height() as u32 * scale) as i32) / 2;
+
+            for py in 0..display.height() {
+                for px in 0..display.width() {
+                    let v = display[(px, py)];
+                    if v != 0 {
+                        canvas.set_draw_color(color(palette.color(v)));
+                        let rect = Rect::new(x + (px as u32 * scale) as i32, y + (py as u32 * scale) as i32, scale, scale);
+                        let _ = canvas.fill_rect(rect);
+                    }
+                }
+            }
+        }
+        None => {
+            let x = preview.x() + margin;
+            let y = preview.y() + margin;
+            osd::draw_text(canvas, x, y, pixel, "No preview");
+        }
+    }
+
+    // Whatever does not fit below the preview is left out.
+    canvas.set_draw_color(color(fg));
+    let chars = (details.width() / (ADVANCE as u32 * pixel)).max(1) as usize;
+    let rows = (details.height() as i32 / line).max(1) as usize;
+    let lines: Vec<String> = launcher.details().iter().flat_map(|text| osd::wrap(text, chars)).collect();
+    for (i, text) in lines.iter().take(rows).enumerate() {
+        osd::draw_text(canvas, details.x(), details.y() + i as i32 * line, pixel, text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::layout;
+
+    #[test]
+    fn test_launcher_layout() {
+        let (list, preview, details) = layout((640, 320));
+        // Font pixels are 3 window pixels wide, so the margin is 12.
+        assert_eq!((12, 54), (list.x(), list.y()));
+        assert_eq!(224, list.width());
+        assert!(list.bottom() <= 320 - 12 - 42);
+
+        assert_eq!(preview.width(), preview.height() * 2);
+        assert!(preview.x() > list.right() && preview.right() <= 640 - 12);
+        assert!(details.y() > preview.bottom() && details.bottom() <= list.bottom());
+    }
+}
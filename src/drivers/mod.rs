@@ -21,5 +21,8 @@
 // SOFTWARE.
 
 pub mod audio;
-pub mod keyboard;
+pub mod input;
+pub mod keypad;
+pub mod launcher;
+pub mod osd;
 pub mod video;
@@ -0,0 +1,313 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::chip8::mem::FONT_DATA;
+use crate::palette::{Palette, Rgb};
+
+// Glyphs missing from the CHIP-8 font, drawn the same way: 4x5 pixels,
+// one byte per row with the pixels in the high nibble.
+const EXTRA_GLYPHS: [(char, [u8; 5]); 33] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0x70, 0x20, 0x20, 0x20, 0x70]),
+    ('J', [0x10, 0x10, 0x10, 0x90, 0xF0]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0xF0, 0x90, 0x90, 0x90, 0xF0]),
+    ('P', [0xF0, 0x90, 0xF0, 0x80, 0x80]),
+    ('Q', [0xF0, 0x90, 0x90, 0xB0, 0xF0]),
+    ('R', [0xF0, 0x90, 0xF0, 0xA0, 0x90]),
+    ('S', [0xF0, 0x80, 0xF0, 0x10, 0xF0]),
+    ('T', [0x70, 0x20, 0x20, 0x20, 0x20]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0xF0]),
+    ('V', [0x90, 0x90, 0x90, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (' ', [0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    ('\'', [0x40, 0x40, 0x00, 0x00, 0x00]),
+    ('!', [0x40, 0x40, 0x40, 0x00, 0x40]),
+    ('(', [0x20, 0x40, 0x40, 0x40, 0x20]),
+    (')', [0x40, 0x20, 0x20, 0x20, 0x40]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    ('-', [0x00, 0x00, 0xF0, 0x00, 0x00]),
+    ('/', [0x10, 0x20, 0x20, 0x40, 0x80]),
+    ('%', [0x90, 0x10, 0x60, 0x80, 0x90]),
+    ('×', [0x00, 0x90, 0x60, 0x90, 0x00]),
+    ('?', [0xE0, 0x10, 0x60, 0x00, 0x40]),
+];
+
+// Width of a character, including the space after it, and height of a
+// line, including the space below it, in font pixels.
+pub(crate) const ADVANCE: usize = 5;
+pub(crate) const LINE_HEIGHT: usize = 7;
+// Size of a font pixel, as a fraction of the shortest side of the window.
+const PIXEL: f32 = 1.0 / 90.0;
+const ALPHA: u8 = 200;
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+/// Returns the 4x5 glyph used to draw `c`. Letters are always drawn in
+/// upper case, and unknown characters as a question mark.
+pub(crate) fn glyph(c: char) -> [u8; 5] {
+    let c = c.to_ascii_uppercase();
+
+    if let Some(digit) = c.to_digit(16) {
+        let i = digit as usize * 5;
+        let mut glyph = [0; 5];
+        glyph.copy_from_slice(&FONT_DATA[i..i + 5]);
+        return glyph;
+    }
+
+    // The question mark comes last.
+    EXTRA_GLYPHS
+        .iter()
+        .find(|&&(g, _)| g == c)
+        .or_else(|| EXTRA_GLYPHS.last())
+        .map(|&(_, glyph)| glyph)
+        .unwrap_or_default()
+}
+
+/// Size of a font pixel in a window of the given size, in window pixels.
+pub(crate) fn pixel_size(window: (u32, u32)) -> u32 {
+    ((window.0.min(window.1) as f32 * PIXEL) as u32).max(1)
+}
+
+/// Size of `lines` of text, in font pixels.
+pub(crate) fn text_size(lines: &[String]) -> (usize, usize) {
+    let width = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) * ADVANCE;
+    (width.saturating_sub(1), (lines.len() * LINE_HEIGHT).saturating_sub(2))
+}
+
+/// Splits `text` into lines of at most `chars` characters, breaking it at
+/// spaces where possible.
+pub(crate) fn wrap(text: &str, chars: usize) -> Vec<String> {
+    let chars = chars.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        let len = line.chars().count();
+
+        if len > 0 && len + 1 + word.len() <= chars {
+            line.push(' ');
+        } else if len > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+
+        // Words longer than a line are cut.
+        while word.len() > chars {
+            lines.push(word.drain(..chars).collect());
+        }
+        line.extend(word);
+    }
+
+    if !line.is_empty() || lines.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Draws `text` with its top left corner at `(x, y)`, using the current
+/// draw colour of the canvas and `pixel` window pixels per font pixel.
+pub(crate) fn draw_text(canvas: &mut Canvas<Window>, x: i32, y: i32, pixel: u32, text: &str) {
+    let rects: Vec<Rect> = text
+        .chars()
+        .enumerate()
+        .flat_map(|(i, c)| {
+            let glyph = glyph(c);
+            (0..5_usize)
+                .flat_map(move |row| (0..4_usize).map(move |col| (i, row, col)))
+                .filter(move |&(_, row, col)| glyph[row] & (0x80 >> col) != 0)
+        })
+        .map(|(i, row, col)| {
+            let px = x + (i * ADVANCE + col) as i32 * pixel as i32;
+            let py = y + row as i32 * pixel as i32;
+            Rect::new(px, py, pixel, pixel)
+        })
+        .collect();
+
+    let _ = canvas.fill_rects(&rects);
+}
+
+/// Performance figures shown in the stats panel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Stats {
+    pub fps: f32,
+    pub instructions_per_second: u32,
+    pub instructions_per_frame: usize,
+    pub pc: u16,
+}
+
+impl Stats {
+    fn lines(&self) -> Vec<String> {
+        vec![
+            format!("FPS {:.1}", self.fps),
+            format!("IPS {}", self.instructions_per_second),
+            format!("IPF {}", self.instructions_per_frame),
+            format!("PC  {:04X}", self.pc),
+        ]
+    }
+}
+
+/// Text drawn on top of the display: a message that goes away after a
+/// couple of seconds in the top left corner, and the stats panel in the
+/// top right one.
+pub(crate) struct Osd {
+    message: Option<(String, Instant)>,
+    stats: Option<Stats>,
+}
+
+impl Osd {
+    pub fn new(stats: bool) -> Self {
+        Osd { message: None, stats: if stats { Some(Stats::default()) } else { None } }
+    }
+
+    pub fn show(&mut self, message: String) {
+        self.message = Some((message, Instant::now() + MESSAGE_DURATION));
+    }
+
+    /// Removes the message once it has been shown long enough. Returns
+    /// true if it did, so the window needs to be redrawn.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.message {
+            Some((_, until)) if now >= until => {
+                self.message = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.stats = match self.stats {
+            Some(_) => None,
+            None => Some(Stats::default()),
+        };
+    }
+
+    /// Replaces the figures in the stats panel. Returns true if the
+    /// panel is visible, so the window needs to be redrawn.
+    pub fn update_stats(&mut self, stats: Stats) -> bool {
+        match &mut self.stats {
+            Some(shown) => {
+                *shown = stats;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, palette: &Palette) {
+        let window = canvas.output_size().expect("could not get window size");
+        let pixel = pixel_size(window);
+
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        if let Some((message, _)) = &self.message {
+            draw_box(canvas, palette, (0, 0), pixel, std::slice::from_ref(message));
+        }
+
+        if let Some(stats) = &self.stats {
+            let lines = stats.lines();
+            let width = (text_size(&lines).0 as u32 + 4) * pixel;
+            draw_box(canvas, palette, (window.0.saturating_sub(width) as i32, 0), pixel, &lines);
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
+
+// Draws lines of text on a translucent box with its top left corner at
+// `at`, leaving a margin of two font pixels around the text.
+fn draw_box(canvas: &mut Canvas<Window>, palette: &Palette, at: (i32, i32), pixel: u32, lines: &[String]) {
+    let color = |c: Rgb, alpha: u8| Color::RGBA(c.0, c.1, c.2, alpha);
+    let (width, height) = text_size(lines);
+    let margin = 2 * pixel as i32;
+
+    canvas.set_draw_color(color(palette.background(), ALPHA));
+    let _ = canvas.fill_rect(Rect::new(at.0, at.1, (width as u32 + 4) * pixel, (height as u32 + 4) * pixel));
+
+    canvas.set_draw_color(color(palette.foreground(), 255));
+    for (i, line) in lines.iter().enumerate() {
+        let y = at.1 + margin + (i * LINE_HEIGHT) as i32 * pixel as i32;
+        draw_text(canvas, at.0 + margin, y, pixel, line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{glyph, text_size, wrap, Osd, Stats};
+    use crate::chip8::mem::FONT_DATA;
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!(FONT_DATA[0xA * 5..0xA * 5 + 5], glyph('a'));
+        assert_eq!(FONT_DATA[7 * 5..7 * 5 + 5], glyph('7'));
+        assert_eq!([0x90, 0x90, 0xF0, 0x90, 0x90], glyph('h'));
+        assert_eq!(glyph('?'), glyph('@'));
+        assert_ne!(glyph('?'), glyph('×'));
+    }
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(vec!["Break the", "bricks."], wrap("Break the bricks.", 10));
+        assert_eq!(vec!["Player 1", "up"], wrap("Player 1 up", 8));
+        assert_eq!(vec!["ABCD", "EF G"], wrap("ABCDEF G", 4));
+        assert_eq!(vec![""], wrap("", 4));
+    }
+
+    #[test]
+    fn test_text_size() {
+        assert_eq!((29, 5), text_size(&["Paused".to_string()]));
+        assert_eq!((39, 26), text_size(&Stats::default().lines()));
+    }
+
+    #[test]
+    fn test_message_expires() {
+        let mut osd = Osd::new(false);
+        osd.show("Paused".to_string());
+
+        let now = Instant::now();
+        assert!(!osd.expire(now));
+        assert!(osd.expire(now + Duration::from_secs(3)));
+        assert!(!osd.expire(now + Duration::from_secs(3)));
+
+        assert!(!osd.update_stats(Stats::default()));
+        osd.toggle_stats();
+        assert!(osd.update_stats(Stats::default()));
+    }
+}
@@ -20,69 +20,387 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use sdl2::pixels::Color;
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
 
-use crate::chip8::cpu::{CHIP8_HEIGHT,CHIP8_WIDTH,Display};
+use crate::chip8::display::Display;
+use crate::drivers::{keypad, launcher};
+use crate::drivers::osd::{Osd, Stats};
+use crate::emulator::Options;
+use crate::filters::crt::{self, CrtFilter, Image};
+use crate::filters::Deflicker;
+use crate::launcher::Launcher;
+use crate::palette::Palette;
 
-const DISPLAY_SCALE: usize = 10;
+pub(crate) const DEFAULT_SCALE: usize = 10;
+const BYTES_PER_PIXEL: usize = 3;
 
 pub(crate) struct VideoDriver {
     canvas: Canvas<Window>,
+    /// Holds one texel per CHIP-8 pixel, or a block of them when CRT
+    /// filters are used. The renderer takes care of scaling it up to
+    /// the size of the window.
+    texture: Texture,
+    /// Width and height of the texture.
+    size: (usize, usize),
+    /// Resolution of the display being shown.
+    display_size: (usize, usize),
+    palette: Palette,
+    deflicker: Deflicker,
+    crt: Vec<CrtFilter>,
+    scale: usize,
+    integer_scaling: bool,
+    /// Copy of the RGB pixels last uploaded to the texture, used to
+    /// find out which rows have changed since.
+    uploaded: Vec<u8>,
+    /// State of the keypad shown on top of the display, if the keypad
+    /// overlay is visible.
+    keypad: Option<[bool; 16]>,
+    osd: Osd,
+    stats: FrameStats,
 }
 
 impl VideoDriver {
-    pub fn new(ctx: &sdl2::Sdl) -> Self {
+    pub fn new(ctx: &sdl2::Sdl, options: &Options) -> Self {
         let video_subsystem = ctx.video().unwrap();
-        let window = video_subsystem
+        let mut window = video_subsystem
             .window(
                 "Chippy - CHIP-8 Interpreter",
-                (DISPLAY_SCALE * CHIP8_WIDTH) as u32,
-                (DISPLAY_SCALE * CHIP8_HEIGHT) as u32,
+                (options.scale * options.platform.display_size.0) as u32,
+                (options.scale * options.platform.display_size.1) as u32,
             )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
             .unwrap();
 
+        if options.fullscreen {
+            window.set_fullscreen(FullscreenType::Desktop).unwrap();
+        }
+
         let mut canvas = window
             .into_canvas()
             .build()
             .unwrap();
 
+        let size = options.platform.display_size;
+        let texture = VideoDriver::create_texture(&canvas, size);
+
         canvas.set_draw_color(Color::RGB(0, 0, 0));
         canvas.clear();
         canvas.present();
 
-        VideoDriver { canvas }
+        VideoDriver {
+            canvas,
+            texture,
+            size,
+            display_size: size,
+            palette: options.palette.clone(),
+            deflicker: Deflicker::new(options.deflicker),
+            crt: options.crt.clone(),
+            scale: options.scale,
+            integer_scaling: options.integer_scaling,
+            // Nothing has been uploaded to the texture yet.
+            uploaded: Vec::new(),
+            keypad: if options.keypad_overlay { Some([false; 16]) } else { None },
+            osd: Osd::new(options.stats_overlay),
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Switches to the palette, filters and scaling of `options`, e.g. when
+    /// another ROM is loaded. The window keeps its size.
+    pub fn set_options(&mut self, options: &Options) {
+        self.palette = options.palette.clone();
+        self.deflicker = Deflicker::new(options.deflicker);
+        self.crt = options.crt.clone();
+        self.scale = options.scale;
+        self.integer_scaling = options.integer_scaling;
+        // Everything has to be uploaded again.
+        self.uploaded.clear();
     }
 
+    /// Must be called whenever the program draws on the display.
     pub fn draw(&mut self, data: &Display) {
-        for y in 0..CHIP8_HEIGHT {
-            for x in 0..CHIP8_WIDTH {
-                self.canvas.set_draw_color(
-                    VideoDriver::color(data[(x, y)])
-                );
-
-                self.canvas.fill_rect(
-                    Rect::new(
-                        (x * DISPLAY_SCALE) as i32,
-                        (y * DISPLAY_SCALE) as i32,
-                        DISPLAY_SCALE as u32,
-                        DISPLAY_SCALE as u32
-                    )
-                )
-                .expect("could not fill rect");
+        if self.deflicker.draw(data) {
+            self.render();
+        }
+    }
+
+    /// Must be called 60 times per second, at the end of every frame.
+    pub fn end_frame(&mut self, data: &Display) {
+        if self.deflicker.end_frame(data) {
+            self.render();
+        }
+    }
+
+    /// Removes the OSD message once it has been shown long enough.
+    /// Returns true if it did, so the window needs to be redrawn.
+    pub fn expire_message(&mut self, now: Instant) -> bool {
+        self.osd.expire(now)
+    }
+
+    fn render(&mut self) {
+        let start = Instant::now();
+
+        let (width, height) = self.deflicker.size();
+        self.display_size = (width, height);
+
+        let pixels = self.deflicker.to_rgb(&self.palette);
+        let Image { width, height, pixels } = crt::apply(&self.crt, Image { width, height, pixels });
+        if self.size != (width, height) {
+            self.resize(width, height);
+        }
+
+        let pitch = width * BYTES_PER_PIXEL;
+
+        if self.uploaded.len() != pixels.len() {
+            self.texture
+                .update(None, &pixels, pitch)
+                .expect("could not update texture");
+            self.uploaded = pixels;
+        } else {
+            // Only the rows that changed are uploaded, and consecutive
+            // ones with a single call.
+            let mut y = 0;
+            while y < height {
+                if !self.row_changed(&pixels, y) {
+                    y += 1;
+                    continue;
+                }
+
+                let first = y;
+                while y < height && self.row_changed(&pixels, y) {
+                    y += 1;
+                }
+
+                let rows = first * pitch..y * pitch;
+                let rect = Rect::new(0, first as i32, width as u32, (y - first) as u32);
+                self.texture
+                    .update(rect, &pixels[rows.clone()], pitch)
+                    .expect("could not update texture");
+                self.uploaded[rows.clone()].copy_from_slice(&pixels[rows]);
             }
         }
 
+        self.present();
+        self.stats.add(start.elapsed());
+    }
+
+    /// Copies the texture to the window again, e.g. after it has been
+    /// resized.
+    pub fn present(&mut self) {
+        let (width, height) = self.canvas.output_size().expect("could not get window size");
+        let rect = viewport(
+            (width, height),
+            (self.display_size.0 as u32, self.display_size.1 as u32),
+            self.integer_scaling,
+        );
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, rect)
+            .expect("could not copy texture");
+
+        if let Some(keys) = &self.keypad {
+            keypad::draw(&mut self.canvas, &self.palette, keys);
+        }
+
+        self.osd.draw(&mut self.canvas, &self.palette);
+
         self.canvas.present();
     }
 
-    fn color(v: u8) -> Color {
-        if v == 0 { Color::RGB(0, 0, 0) }
-        else { Color::RGB(255, 255, 0) }
+    /// Draws the launcher in place of the display, until `present` is
+    /// called.
+    pub fn draw_launcher(&mut self, launcher: &Launcher) {
+        launcher::draw(&mut self.canvas, &self.palette, launcher);
+        self.canvas.present();
+    }
+
+    /// Shows or hides the on-screen keypad.
+    pub fn toggle_keypad(&mut self) {
+        self.keypad = match self.keypad {
+            Some(_) => None,
+            None => Some([false; 16]),
+        };
+
+        self.present();
+    }
+
+    /// Highlights the pressed buttons on the on-screen keypad, if it is
+    /// visible.
+    pub fn update_keypad(&mut self, keys: &[bool; 16]) {
+        match self.keypad {
+            Some(shown) if shown != *keys => {
+                self.keypad = Some(*keys);
+                self.present();
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the button of the on-screen keypad at the window
+    /// coordinates `(x, y)`.
+    pub fn keypad_button_at(&self, x: i32, y: i32) -> Option<usize> {
+        let window = self.canvas.output_size().ok()?;
+        self.keypad.and_then(|_| keypad::button_at(window, x, y))
+    }
+
+    /// Shows a message on top of the display for a couple of seconds.
+    pub fn show_message(&mut self, message: String) {
+        self.osd.show(message);
+        self.present();
+    }
+
+    /// Shows or hides the stats panel.
+    pub fn toggle_stats(&mut self) {
+        self.osd.toggle_stats();
+        self.present();
+    }
+
+    /// Updates the stats panel, if it is visible.
+    pub fn update_stats(&mut self, stats: Stats) {
+        if self.osd.update_stats(stats) {
+            self.present();
+        }
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            eprintln!("Could not set window title: {}", e);
+        }
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+
+        if let Err(e) = window.set_fullscreen(fullscreen) {
+            eprintln!("Could not toggle fullscreen: {}", e);
+        }
+
+        self.present();
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Number of window pixels used to render each CHIP-8 pixel in
+    /// screenshots and recordings.
+    pub fn scale(&self) -> usize {
+        self.scale
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+
+    fn create_texture(canvas: &Canvas<Window>, size: (usize, usize)) -> Texture {
+        canvas
+            .texture_creator()
+            .create_texture_streaming(PixelFormatEnum::RGB24, size.0 as u32, size.1 as u32)
+            .unwrap()
+    }
+
+    /// Replaces the texture when the display changes resolution.
+    fn resize(&mut self, width: usize, height: usize) {
+        let texture = VideoDriver::create_texture(&self.canvas, (width, height));
+        let old = std::mem::replace(&mut self.texture, texture);
+        // The canvas that created the old texture is still alive.
+        unsafe { old.destroy() };
+
+        self.size = (width, height);
+        self.uploaded.clear();
+    }
+
+    fn row_changed(&self, pixels: &[u8], y: usize) -> bool {
+        let pitch = self.size.0 * BYTES_PER_PIXEL;
+        pixels[y * pitch..(y + 1) * pitch] != self.uploaded[y * pitch..(y + 1) * pitch]
+    }
+}
+
+/// Returns the largest area of the window where the display fits while
+/// keeping square pixels, centered so the rest of the window is left as
+/// black bars. With `integer_scaling`, each CHIP-8 pixel is always drawn
+/// as a whole number of window pixels.
+fn viewport(window: (u32, u32), display: (u32, u32), integer_scaling: bool) -> Rect {
+    let mut scale = f64::min(
+        window.0 as f64 / display.0 as f64,
+        window.1 as f64 / display.1 as f64,
+    );
+
+    if integer_scaling {
+        scale = scale.floor().max(1.0);
+    }
+
+    let width = (display.0 as f64 * scale).round() as u32;
+    let height = (display.1 as f64 * scale).round() as u32;
+
+    Rect::new(
+        (window.0 as i32 - width as i32) / 2,
+        (window.1 as i32 - height as i32) / 2,
+        width,
+        height,
+    )
+}
+
+/// Keeps track of how long it takes to draw a frame.
+#[derive(Default)]
+pub(crate) struct FrameStats {
+    frames: u32,
+    total: Duration,
+    worst: Duration,
+}
+
+impl FrameStats {
+    fn add(&mut self, elapsed: Duration) {
+        self.frames += 1;
+        self.total += elapsed;
+        self.worst = self.worst.max(elapsed);
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let average = self.total.checked_div(self.frames).unwrap_or_default();
+
+        write!(
+            f,
+            "{} frames drawn, {:?} on average, {:?} at worst",
+            self.frames, average, self.worst
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::rect::Rect;
+
+    use super::viewport;
+
+    #[test]
+    fn test_viewport_letterboxes() {
+        assert_eq!(Rect::new(0, 0, 640, 320), viewport((640, 320), (64, 32), false));
+        assert_eq!(Rect::new(0, 140, 800, 400), viewport((800, 680), (64, 32), false));
+        assert_eq!(Rect::new(80, 0, 640, 320), viewport((800, 320), (64, 32), false));
+        assert_eq!(Rect::new(0, 140, 800, 400), viewport((800, 680), (128, 64), false));
+    }
+
+    #[test]
+    fn test_viewport_integer_scaling() {
+        assert_eq!(Rect::new(16, 148, 768, 384), viewport((800, 680), (64, 32), true));
+        assert_eq!(Rect::new(16, 148, 768, 384), viewport((800, 680), (128, 64), true));
+        assert_eq!(Rect::new(-22, -11, 64, 32), viewport((20, 10), (64, 32), true));
     }
 }
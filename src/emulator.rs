@@ -22,16 +22,295 @@
 
 extern crate sdl2;
 
+use std::path::{Path, PathBuf};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub use crate::chip8::mem::ROM_SIZE;
+pub use crate::chip8::mem::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE};
+pub use crate::chip8::display::Display;
+pub use crate::chip8::platform::{Platform, PLATFORMS};
+pub use crate::chip8::quirks::Quirks;
 use crate::chip8;
+use crate::chip8::cpu::Cpu;
+use crate::config::Config;
 use crate::drivers::audio::AudioDriver;
-use crate::drivers::keyboard::KeyboardDriver;
-use crate::drivers::video::VideoDriver;
+use crate::drivers::input::{Command, InputDriver, MenuKey};
+use crate::drivers::osd::Stats;
+use crate::drivers::video::{DEFAULT_SCALE, VideoDriver};
+use crate::filters::{CrtFilter, DeflickerMode};
+use crate::input::InputQueue;
+use crate::keymap::Keymap;
+use crate::launcher::{Launcher, DEFAULT_GAMES_DIR};
+use crate::palette::Palette;
+use crate::recorder::Recorder;
+use crate::rom;
+use crate::sound::Tone;
+use crate::watch::FileWatcher;
+use crate::wav::WavWriter;
 
-const SLEEP: u64 = 1;
+const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+const DEFAULT_DEAD_ZONE: f32 = 0.3;
+const FRAME_DURATION: Duration = Duration::from_micros(1_000_000 / 60);
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+/// Settings that change how the emulator runs.
+#[derive(Clone)]
+pub struct Options {
+    /// Size of a CHIP-8 pixel in the initial window, in screen pixels.
+    /// Screenshots and recordings use it as well.
+    pub scale: usize,
+    /// Only scales the display by whole numbers when the window is
+    /// resized, so that all pixels have the same size.
+    pub integer_scaling: bool,
+    /// Starts in fullscreen mode.
+    pub fullscreen: bool,
+    /// Colours used to draw the display.
+    pub palette: Palette,
+    /// How to reduce the flicker caused by sprites being erased and
+    /// drawn again.
+    pub deflicker: DeflickerMode,
+    /// Post-processing filters applied to the display in the window, in
+    /// order. Screenshots and recordings are left unfiltered.
+    pub crt: Vec<CrtFilter>,
+    /// Shows a keypad on top of the display, which highlights the pressed
+    /// buttons and can be clicked with the mouse.
+    pub keypad_overlay: bool,
+    /// Shows the frames and instructions per second, the instructions per
+    /// frame and the program counter on top of the display.
+    pub stats_overlay: bool,
+    /// Host keys and controller inputs bound to the CHIP-8 keypad.
+    pub keys: Keymap,
+    /// How far an analog stick has to be pushed before it presses a
+    /// keypad button, from 0 to 1.
+    pub dead_zone: f32,
+    /// Sound of the buzzer.
+    pub tone: Tone,
+    /// Starts with the buzzer muted.
+    pub muted: bool,
+    /// Memory map and display of the machine, which also set how large
+    /// programs can be.
+    pub platform: Platform,
+    /// Runs the programs that start with the jump into the hi-res patch
+    /// of the VIP on its 64x64 display. Off once a platform is picked.
+    pub detect_hires: bool,
+    /// Behaviours that differ between CHIP-8 interpreters.
+    pub quirks: Quirks,
+    /// Number of instructions executed in every frame, 60 times per
+    /// second, which sets how fast the emulator runs.
+    pub instructions_per_frame: usize,
+    /// How fast frames go by, 1 being 60 frames per second.
+    pub speed: f32,
+    /// Speed of fast-forward, or 0 to run as fast as possible.
+    pub fast_forward: f32,
+    /// Speed of slow motion.
+    pub slow_motion: f32,
+    /// File the ROM was read from, which is read again to reload it.
+    pub rom_path: Option<PathBuf>,
+    /// Reloads the ROM whenever its file changes.
+    pub watch: bool,
+    /// Directory listed by the launcher.
+    pub games_dir: PathBuf,
+    /// File where the gameplay is recorded to, if any. It is an animated
+    /// GIF, or a Y4M stream when the name ends in `.y4m` or is `-`.
+    pub record: Option<PathBuf>,
+    /// WAV file where the sound is recorded to, if any.
+    pub record_audio: Option<PathBuf>,
+    /// Runs without a window, an audio device or any input, as fast as
+    /// possible, for `frames` frames.
+    pub headless: bool,
+    pub frames: u64,
+    /// File where the keypad events are logged to, if any, or `-` for the
+    /// standard output.
+    pub input_log: Option<PathBuf>,
+    /// Prints how long it took to draw the frames to the standard error
+    /// when the emulator exits.
+    pub frame_stats: bool,
+    /// Global configuration, from which the options of every ROM are
+    /// worked out.
+    pub config: Config,
+    /// Settings given on the command line, which go over everything else.
+    pub flags: Config,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scale: DEFAULT_SCALE,
+            integer_scaling: false,
+            fullscreen: false,
+            palette: Palette::default(),
+            deflicker: DeflickerMode::None,
+            crt: Vec::new(),
+            keypad_overlay: false,
+            stats_overlay: false,
+            keys: Keymap::default(),
+            dead_zone: DEFAULT_DEAD_ZONE,
+            tone: Tone::default(),
+            muted: false,
+            platform: Platform::default(),
+            detect_hires: true,
+            quirks: Quirks::default(),
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            speed: 1.0,
+            fast_forward: 0.0,
+            slow_motion: 0.25,
+            rom_path: None,
+            watch: false,
+            games_dir: PathBuf::from(DEFAULT_GAMES_DIR),
+            record: None,
+            record_audio: None,
+            headless: false,
+            frames: DEFAULT_HEADLESS_FRAMES,
+            input_log: None,
+            frame_stats: false,
+            config: Config::default(),
+            flags: Config::default(),
+        }
+    }
+}
+
+impl Options {
+    /// The options for running the ROM at `path`, whose contents are
+    /// `data`: the defaults, with the global configuration, the settings
+    /// that come with the ROM, its section of the configuration and the
+    /// command line flags on top. Whatever does not come from those, such
+    /// as the recordings, is kept.
+    pub fn for_rom(&self, path: Option<&Path>, data: Option<&[u8]>) -> Result<Options, String> {
+        let mut options = Options {
+            rom_path: path.map(Path::to_path_buf),
+            watch: self.watch,
+            games_dir: self.games_dir.clone(),
+            record: self.record.clone(),
+            record_audio: self.record_audio.clone(),
+            headless: self.headless,
+            frames: self.frames,
+            input_log: self.input_log.clone(),
+            frame_stats: self.frame_stats,
+            config: self.config.clone(),
+            flags: self.flags.clone(),
+            ..Options::default()
+        };
+
+        let rom_settings = match (path, data) {
+            (Some(path), Some(data)) => rom::settings(path, data)?,
+            _ => Vec::new(),
+        };
+        let name = path.and_then(Path::file_name).and_then(|n| n.to_str());
+        self.config.apply_with(&mut options, name, &rom_settings, &self.flags)?;
+
+        Ok(options)
+    }
+}
+
+const TITLE: &str = "Chippy - CHIP-8 Interpreter";
+// How often input is read while the emulator is paused.
+const PAUSED_POLL: Duration = Duration::from_millis(10);
+// How many entries Page Up and Page Down skip in the launcher.
+const LAUNCHER_PAGE: isize = 10;
+// How often the stats panel is updated.
+const STATS_PERIOD: Duration = Duration::from_secs(1);
+
+/// Speeds that can be picked with hotkeys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Speed {
+    Normal,
+    SlowMotion,
+    FastForward,
+}
+
+/// State of the speed controls.
+struct Pace {
+    speed: Speed,
+    paused: bool,
+    /// Runs a single frame while paused.
+    step: bool,
+    instructions_per_frame: usize,
+}
+
+impl Pace {
+    fn new(options: &Options) -> Self {
+        Pace {
+            speed: Speed::Normal,
+            paused: false,
+            step: false,
+            instructions_per_frame: options.instructions_per_frame,
+        }
+    }
+
+    fn toggle(&mut self, speed: Speed) {
+        self.speed = if self.speed == speed { Speed::Normal } else { speed };
+    }
+
+    /// How fast frames go by, 1 being 60 per second and 0 as fast as
+    /// possible.
+    fn factor(&self, options: &Options) -> f32 {
+        match self.speed {
+            Speed::Normal => options.speed,
+            Speed::SlowMotion => options.slow_motion,
+            Speed::FastForward => options.fast_forward,
+        }
+    }
+
+    /// Time between frames, or None to run them as fast as possible.
+    fn frame_duration(&self, options: &Options) -> Option<Duration> {
+        match self.factor(options) {
+            f if f > 0.0 => Some(FRAME_DURATION.div_f32(f)),
+            _ => None,
+        }
+    }
+
+    /// Describes the current speed, e.g. `2×`.
+    fn speed_label(&self, options: &Options) -> String {
+        match self.factor(options) {
+            _ if self.paused => "Paused".to_string(),
+            f if f > 0.0 => format!("{}×", f),
+            _ => "Fast-forward".to_string(),
+        }
+    }
+
+    fn title(&self, options: &Options) -> String {
+        format!(
+            "{} - {} - {} instructions per frame",
+            TITLE,
+            self.speed_label(options),
+            self.instructions_per_frame
+        )
+    }
+}
+
+/// Measures how many frames and instructions are actually run per
+/// second, for the stats panel.
+struct Meter {
+    since: Instant,
+    frames: u32,
+    instructions: usize,
+}
+
+impl Meter {
+    fn new(now: Instant) -> Self {
+        Meter { since: now, frames: 0, instructions: 0 }
+    }
+
+    fn add(&mut self, instructions: usize) {
+        self.frames += 1;
+        self.instructions += instructions;
+    }
+
+    /// Returns the frames and instructions per second once every
+    /// `STATS_PERIOD`, and starts counting again.
+    fn sample(&mut self, now: Instant) -> Option<(f32, u32)> {
+        let elapsed = now.duration_since(self.since);
+        if elapsed < STATS_PERIOD {
+            return None;
+        }
+
+        let seconds = elapsed.as_secs_f32();
+        let sample = (self.frames as f32 / seconds, (self.instructions as f32 / seconds).round() as u32);
+        *self = Meter::new(now);
+        Some(sample)
+    }
+}
 
 /// Unit struct that only provides one method.
 pub struct Emulator;
@@ -39,36 +318,483 @@ pub struct Emulator;
 impl Emulator {
     /// Runs the CHIP-8 emulator with the provided ROM until the
     /// ESC key is pressed (or until it crashes, which may also
-    /// happen).
-    pub fn run(rom: [u8; ROM_SIZE]) -> Result<(), String> {
+    /// happen). Without a ROM, the launcher is shown first.
+    pub fn run(rom: Option<Vec<u8>>, options: &Options) -> Result<(), String> {
+        if options.headless {
+            let rom = rom.ok_or("a ROM is needed to run headless")?;
+            return Emulator::run_headless(&rom, options);
+        }
+
         let sdl_context = sdl2::init()?;
 
-        let mut chip8 = chip8::new_chip8(rom);
-        let mut keyboard = KeyboardDriver::new(&sdl_context);
-        let mut video = VideoDriver::new(&sdl_context);
-        let audio = AudioDriver::new(&sdl_context);
+        // Picking another ROM in the launcher switches to its own options.
+        let mut options = options.clone();
+        let mut loaded = rom.is_some();
+        let mut rom = rom.unwrap_or_default();
+        let mut rom_path = options.rom_path.clone();
+        let mut chip8 = chip8::new_chip8(&rom, &options.platform, options.quirks);
+        let mut input = InputDriver::new(&sdl_context, &options)?;
+        let mut video = VideoDriver::new(&sdl_context, &options);
+        let mut audio = AudioDriver::new(&sdl_context, options.tone);
+        if options.muted {
+            audio.toggle_mute();
+        }
+
+        let mut recorder = match &options.record {
+            Some(path) => Some(Recorder::new(path, video.palette(), (chip8.display.width(), chip8.display.height()), video.scale())?),
+            None => None,
+        };
+        let mut wav = match &options.record_audio {
+            Some(path) => Some(WavWriter::create(path, options.tone)?),
+            None => None,
+        };
+        let mut queue = InputQueue::new();
+        if let Some(path) = &options.input_log {
+            queue.log_to(path)?;
+        }
+
+        let mut frame = 0;
+        let mut next_frame = Instant::now();
+        let mut pace = Pace::new(&options);
+        let mut meter = Meter::new(next_frame);
+        video.set_title(&pace.title(&options));
+
+        let watching = options.watch;
+        let watch = |path: &Option<PathBuf>| match path {
+            Some(path) if watching => Some(FileWatcher::new(path)),
+            _ => None,
+        };
+        let mut watcher = watch(&rom_path);
+
+        let mut launcher = if loaded { None } else { Some(Launcher::open(&options.games_dir, &options)?) };
+        if let Some(l) = &launcher {
+            input.set_menu(true);
+            video.draw_launcher(l);
+        }
+
+        'running: while let Ok(mut commands) = input.read(&mut queue, |x, y| video.keypad_button_at(x, y)) {
+            if let Some(w) = watcher.as_mut() {
+                if w.changed(Instant::now()) {
+                    commands.push(Command::Reload);
+                }
+            }
+
+            let redraw_launcher = !commands.is_empty();
+
+            for command in commands {
+                let message = match command {
+                    Command::OpenLauncher => match Launcher::open(&options.games_dir, &options) {
+                        Ok(l) => {
+                            launcher = Some(l);
+                            input.set_menu(true);
+                            None
+                        }
+                        Err(e) => {
+                            eprintln!("Could not open the launcher: {}", e);
+                            Some("Could not open the launcher".to_string())
+                        }
+                    },
+                    Command::Menu(key) => match (launcher.as_mut(), key) {
+                        (Some(l), MenuKey::Up) => {
+                            l.move_by(-1, &options);
+                            None
+                        }
+                        (Some(l), MenuKey::Down) => {
+                            l.move_by(1, &options);
+                            None
+                        }
+                        (Some(l), MenuKey::PageUp) => {
+                            l.move_by(-LAUNCHER_PAGE, &options);
+                            None
+                        }
+                        (Some(l), MenuKey::PageDown) => {
+                            l.move_by(LAUNCHER_PAGE, &options);
+                            None
+                        }
+                        (Some(l), MenuKey::Select) => {
+                            let entry = l.selected();
+                            match Emulator::open(&entry.path, &mut options, &mut input, &mut video, &mut audio, &mut pace) {
+                                Ok(data) => {
+                                    let message = format!("{} loaded", entry.name);
+                                    rom = data;
+                                    loaded = true;
+                                    rom_path = Some(entry.path.clone());
+                                    watcher = watch(&rom_path);
+                                    launcher = None;
+                                    input.set_menu(false);
+                                    Emulator::reset(&mut chip8, &rom, &options, &mut video);
+                                    video.present();
+                                    Some(message)
+                                }
+                                Err(e) => {
+                                    eprintln!("Could not load the ROM: {}", e);
+                                    None
+                                }
+                            }
+                        }
+                        // Leaving the launcher before anything was loaded
+                        // means there is nothing to run.
+                        (Some(_), MenuKey::Back) if !loaded => break 'running,
+                        (_, MenuKey::Back) => {
+                            launcher = None;
+                            input.set_menu(false);
+                            video.present();
+                            None
+                        }
+                        (None, _) => None,
+                    },
+                    Command::TogglePause => {
+                        pace.paused = !pace.paused;
+                        Some(pace.speed_label(&options))
+                    }
+                    Command::FrameAdvance if pace.paused => {
+                        pace.step = true;
+                        None
+                    }
+                    Command::FrameAdvance => {
+                        pace.paused = true;
+                        Some(pace.speed_label(&options))
+                    }
+                    Command::Reset => {
+                        Emulator::reset(&mut chip8, &rom, &options, &mut video);
+                        Some("Reset".to_string())
+                    }
+                    Command::Reload => match rom_path.clone().map(|p| {
+                        Emulator::open(&p, &mut options, &mut input, &mut video, &mut audio, &mut pace)
+                    }) {
+                        Some(Ok(data)) => {
+                            rom = data;
+                            Emulator::reset(&mut chip8, &rom, &options, &mut video);
+                            Some("ROM reloaded".to_string())
+                        }
+                        Some(Err(e)) => {
+                            eprintln!("Could not reload the ROM: {}", e);
+                            Some("Could not reload the ROM".to_string())
+                        }
+                        None => None,
+                    },
+                    Command::ToggleSlowMotion => {
+                        pace.toggle(Speed::SlowMotion);
+                        Some(format!("Speed {}", pace.speed_label(&options)))
+                    }
+                    Command::ToggleFastForward => {
+                        pace.toggle(Speed::FastForward);
+                        Some(format!("Speed {}", pace.speed_label(&options)))
+                    }
+                    Command::MoreInstructions => {
+                        pace.instructions_per_frame += 1;
+                        Some(format!("{} instructions per frame", pace.instructions_per_frame))
+                    }
+                    Command::FewerInstructions => {
+                        pace.instructions_per_frame = pace.instructions_per_frame.saturating_sub(1).max(1);
+                        Some(format!("{} instructions per frame", pace.instructions_per_frame))
+                    }
+                    Command::ToggleMute => {
+                        let muted = audio.toggle_mute();
+                        Some(format!("Sound {}", if muted { "muted" } else { "on" }))
+                    }
+                    Command::ToggleStats => {
+                        video.toggle_stats();
+                        None
+                    }
+                    Command::ToggleKeypad => {
+                        video.toggle_keypad();
+                        None
+                    }
+                    Command::ToggleFullscreen => {
+                        video.toggle_fullscreen();
+                        None
+                    }
+                    Command::Screenshot => Some(Emulator::save_screenshot(&chip8.display, &video)),
+                    Command::Redraw => {
+                        video.present();
+                        None
+                    }
+                };
+
+                if let Some(message) = message {
+                    video.show_message(message);
+                }
+                video.set_title(&pace.title(&options));
+            }
+
+            let now = Instant::now();
+
+            // Messages go away on time, whether the emulator is running or
+            // paused.
+            if video.expire_message(now) && launcher.is_none() {
+                video.present();
+            }
+
+            if let Some(l) = &launcher {
+                if redraw_launcher {
+                    video.draw_launcher(l);
+                }
+                thread::sleep(PAUSED_POLL);
+                next_frame = now;
+                continue;
+            }
+
+            if pace.paused && !pace.step {
+                thread::sleep(PAUSED_POLL);
+                next_frame = now;
+                continue;
+            }
+
+            let duration = pace.frame_duration(&options);
+            if duration.is_some() && now < next_frame {
+                thread::sleep(next_frame - now);
+                continue;
+            }
+            pace.step = false;
+
+            // Input only changes between frames, so a program always sees
+            // the same keys at the same point of its execution.
+            queue.apply(frame, &mut chip8.keypad);
+            video.update_keypad(&chip8.keypad);
+
+            let beeped = match Emulator::run_frame(&mut chip8, pace.instructions_per_frame, |display| {
+                video.draw(display)
+            }) {
+                Ok(beeped) => beeped,
+                Err(e) => {
+                    // The program can't go on, but it can still be reset,
+                    // reloaded or swapped for another one.
+                    eprintln!("The program stopped: {}", e);
+                    video.show_message(format!("Stopped: {}", e));
+                    pace.paused = true;
+                    video.set_title(&pace.title(&options));
+                    false
+                }
+            };
+            audio.end_frame(beeped);
+            video.end_frame(&chip8.display);
+
+            if let Some(r) = recorder.as_mut() {
+                if let Err(e) = r.capture(&chip8.display) {
+                    eprintln!("Recording stopped: {}", e);
+                    recorder = None;
+                }
+            }
+
+            if let Some(w) = wav.as_mut() {
+                if let Err(e) = w.frame(beeped) {
+                    eprintln!("Audio recording stopped: {}", e);
+                    wav = None;
+                }
+            }
+
+            meter.add(pace.instructions_per_frame);
+            if let Some((fps, instructions_per_second)) = meter.sample(now) {
+                video.update_stats(Stats {
+                    fps,
+                    instructions_per_second,
+                    instructions_per_frame: pace.instructions_per_frame,
+                    pc: chip8.pc(),
+                });
+            }
+
+            frame += 1;
+            next_frame += duration.unwrap_or_default();
+
+            // Don't try to catch up after a long stall, e.g. while the
+            // window was being dragged.
+            if now > next_frame + MAX_LAG {
+                next_frame = now;
+            }
+        }
+
+        if options.frame_stats {
+            eprintln!("{}", video.stats());
+        }
+
+        if let Some(w) = wav {
+            w.finish()?;
+        }
+
+        match recorder {
+            Some(r) => r.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the ROM for `options.frames` frames as fast as possible,
+    /// without a window, an audio device or any input. Only useful to
+    /// record the video or the audio.
+    fn run_headless(rom: &[u8], options: &Options) -> Result<(), String> {
+        let mut chip8 = chip8::new_chip8(rom, &options.platform, options.quirks);
+
+        let mut recorder = match &options.record {
+            Some(path) => Some(Recorder::new(path, &options.palette, (chip8.display.width(), chip8.display.height()), options.scale)?),
+            None => None,
+        };
+        let mut wav = match &options.record_audio {
+            Some(path) => Some(WavWriter::create(path, options.tone)?),
+            None => None,
+        };
+
+        for _ in 0..options.frames {
+            let beeped = Emulator::run_frame(&mut chip8, options.instructions_per_frame, |_| {})?;
+
+            if let Some(r) = recorder.as_mut() {
+                r.capture(&chip8.display)?;
+            }
+
+            if let Some(w) = wav.as_mut() {
+                w.frame(beeped)?;
+            }
+        }
+
+        if let Some(w) = wav {
+            w.finish()?;
+        }
+
+        match recorder {
+            Some(r) => r.finish(),
+            None => Ok(()),
+        }
+    }
+
+    /// Loads the ROM at `path` with the options worked out for it, and
+    /// switches the drivers and the speed over to them.
+    fn open(
+        path: &Path,
+        options: &mut Options,
+        input: &mut InputDriver,
+        video: &mut VideoDriver,
+        audio: &mut AudioDriver,
+        pace: &mut Pace,
+    ) -> Result<Vec<u8>, String> {
+        let (rom, rom_options) = rom::open(path, options)?;
+
+        input.set_keys(&rom_options)?;
+        video.set_options(&rom_options);
+        audio.set_tone(rom_options.tone);
+        pace.instructions_per_frame = rom_options.instructions_per_frame;
+        *options = rom_options;
+
+        Ok(rom)
+    }
 
-        loop {
-            chip8.fetch_decode_execute();
+    /// Starts the program over from a fresh copy of `rom`. Keys that are
+    /// being held stay down.
+    fn reset(chip8: &mut Cpu, rom: &[u8], options: &Options, video: &mut VideoDriver) {
+        let keypad = chip8.keypad;
+        *chip8 = chip8::new_chip8(rom, &options.platform, options.quirks);
+        chip8.keypad = keypad;
+        video.draw(&chip8.display);
+    }
+
+    /// Executes one frame worth of instructions and then ticks the timers.
+    /// `draw` is called whenever the program draws on the display. Returns
+    /// whether the buzzer was on at any point during the frame, in which
+    /// case it sounds for the whole frame, or the error that stopped the
+    /// program.
+    pub(crate) fn run_frame<F: FnMut(&Display)>(chip8: &mut Cpu, instructions: usize, mut draw: F) -> Result<bool, String> {
+        let mut beeped = chip8.beep;
+
+        for _ in 0..instructions {
+            chip8.fetch_decode_execute()?;
+            beeped |= chip8.beep;
 
             if chip8.draw {
-                video.draw(&chip8.display);
+                draw(&chip8.display);
                 chip8.draw = false;
             }
+        }
 
-            if chip8.beep {
-                audio.start_beeping();
-            } else {
-                audio.stop_beeping();
-            }
+        chip8.tick_timers();
+        Ok(beeped)
+    }
 
-            if let Err(_) = keyboard.read(&mut chip8.keypad) {
-                break;
+    /// Saves the display to `chippy-<timestamp>.png` in the current
+    /// directory, using the same palette and scale as the window. Returns
+    /// a message to show on screen.
+    fn save_screenshot(display: &Display, video: &VideoDriver) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let file_name = format!("chippy-{}.png", timestamp);
+
+        match display.save_png(Path::new(&file_name), video.palette(), video.scale()) {
+            Ok(()) => {
+                eprintln!("Screenshot saved to {}", file_name);
+                "Screenshot saved".to_string()
+            }
+            Err(e) => {
+                eprintln!("Could not save screenshot: {}", e);
+                "Could not save screenshot".to_string()
             }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use super::{Meter, Options, Pace, Speed};
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_pace() {
+        let options = Options::default();
+        let mut pace = Pace::new(&options);
+
+        assert_eq!(Some(16), pace.frame_duration(&options).map(|d| d.as_millis()));
+        assert!(pace.title(&options).ends_with(" - 1× - 10 instructions per frame"));
+
+        pace.toggle(Speed::SlowMotion);
+        assert_eq!(Some(66), pace.frame_duration(&options).map(|d| d.as_millis()));
+        assert!(pace.title(&options).contains(" - 0.25× - "));
+
+        pace.toggle(Speed::FastForward);
+        assert_eq!(None, pace.frame_duration(&options));
+        assert!(pace.title(&options).contains(" - Fast-forward - "));
+
+        pace.toggle(Speed::FastForward);
+        pace.paused = true;
+        assert_eq!(Speed::Normal, pace.speed);
+        assert!(pace.title(&options).contains(" - Paused - "));
+    }
+
+    #[test]
+    fn test_for_rom() {
+        let options = Options {
+            config: toml::from_str("[roms.ZERO.emulation]\ninstructions-per-frame = 15").unwrap(),
+            flags: toml::from_str("video.theme = \"amber\"").unwrap(),
+            frames: 42,
+            ..Options::default()
+        };
+
+        // The ROM's section of the configuration and the flags both count,
+        // and the options that don't come from them are kept.
+        let rom_options = options.for_rom(Some(Path::new("games/ZERO")), None).unwrap();
+        assert_eq!(15, rom_options.instructions_per_frame);
+        assert_eq!(Palette::theme("amber").unwrap(), rom_options.palette);
+        assert_eq!(42, rom_options.frames);
+        assert_eq!(Some(Path::new("games/ZERO")), rom_options.rom_path.as_deref());
+
+        let other_options = rom_options.for_rom(Some(Path::new("games/ONE")), None).unwrap();
+        assert_eq!(10, other_options.instructions_per_frame);
+        assert_eq!(Palette::theme("amber").unwrap(), other_options.palette);
+    }
+
+    #[test]
+    fn test_meter() {
+        let start = Instant::now();
+        let mut meter = Meter::new(start);
 
-            thread::sleep(Duration::from_millis(SLEEP));
+        for _ in 0..30 {
+            meter.add(10);
         }
+        assert_eq!(None, meter.sample(start + Duration::from_millis(500)));
 
-        Ok(())
+        meter.add(10);
+        meter.add(10);
+        assert_eq!(Some((16.0, 160)), meter.sample(start + Duration::from_secs(2)));
+        assert_eq!(None, meter.sample(start + Duration::from_secs(2)));
     }
 }
@@ -0,0 +1,282 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::str::FromStr;
+
+// Every CHIP-8 pixel becomes a block of this many texels on each side,
+// so the filters have some room to draw on.
+pub(crate) const CRT_SCALE: usize = 6;
+
+// Brightness kept by the texels darkened by scanlines and by the grid.
+const SCANLINE_LEVEL: f32 = 0.55;
+const GRID_LEVEL: f32 = 0.7;
+// How much of the blurred image is added back by bloom, and how far the
+// glow spreads (in texels).
+const BLOOM_STRENGTH: f32 = 0.6;
+const BLOOM_RADIUS: usize = CRT_SCALE;
+// The lower this is, the more the image bulges.
+const CURVATURE: f32 = 5.0;
+
+/// Post-processing filters that make the display look like it is shown
+/// on an old CRT screen. They are applied in the order they are given.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CrtFilter {
+    /// Darkens the bottom of every row of pixels.
+    Scanlines,
+    /// Draws thin dark lines between pixels.
+    Grid,
+    /// Makes lit pixels glow into their neighbours.
+    Bloom,
+    /// Bends the image like the curved glass of a CRT.
+    Curvature,
+}
+
+impl CrtFilter {
+    /// Parses a comma separated list of filter names. `crt` is short for
+    /// `scanlines,bloom,curvature`.
+    pub fn parse_chain(s: &str) -> Result<Vec<CrtFilter>, String> {
+        let mut chain = Vec::new();
+
+        for name in s.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            match name {
+                "none" => {}
+                "crt" => chain.extend(&[CrtFilter::Scanlines, CrtFilter::Bloom, CrtFilter::Curvature]),
+                _ => chain.push(name.parse()?),
+            }
+        }
+
+        Ok(chain)
+    }
+}
+
+impl FromStr for CrtFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanlines" => Ok(CrtFilter::Scanlines),
+            "grid" => Ok(CrtFilter::Grid),
+            "bloom" => Ok(CrtFilter::Bloom),
+            "curvature" => Ok(CrtFilter::Curvature),
+            _ => Err(format!("unknown filter: {}", s)),
+        }
+    }
+}
+
+/// A packed RGB24 image.
+pub(crate) struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Runs the filters in `chain` on an image with one pixel per CHIP-8
+/// pixel. Unless the chain is empty, the result is `CRT_SCALE` times
+/// larger on each side.
+pub(crate) fn apply(chain: &[CrtFilter], image: Image) -> Image {
+    if chain.is_empty() {
+        return image;
+    }
+
+    let mut image = upscale(&image, CRT_SCALE);
+
+    for filter in chain {
+        match filter {
+            CrtFilter::Scanlines => scanlines(&mut image),
+            CrtFilter::Grid => grid(&mut image),
+            CrtFilter::Bloom => bloom(&mut image),
+            CrtFilter::Curvature => image = curvature(&image),
+        }
+    }
+
+    image
+}
+
+fn upscale(image: &Image, scale: usize) -> Image {
+    let width = image.width * scale;
+    let height = image.height * scale;
+    let mut pixels = Vec::with_capacity(width * height * 3);
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = ((y / scale) * image.width + x / scale) * 3;
+            pixels.extend_from_slice(&image.pixels[i..i + 3]);
+        }
+    }
+
+    Image { width, height, pixels }
+}
+
+fn darken(pixel: &mut [u8], level: f32) {
+    pixel.iter_mut().for_each(|c| *c = (*c as f32 * level) as u8);
+}
+
+fn scanlines(image: &mut Image) {
+    let pitch = image.width * 3;
+
+    for (y, row) in image.pixels.chunks_mut(pitch).enumerate() {
+        if y % CRT_SCALE >= CRT_SCALE * 2 / 3 {
+            row.chunks_mut(3).for_each(|p| darken(p, SCANLINE_LEVEL));
+        }
+    }
+}
+
+fn grid(image: &mut Image) {
+    let pitch = image.width * 3;
+
+    for (y, row) in image.pixels.chunks_mut(pitch).enumerate() {
+        for (x, pixel) in row.chunks_mut(3).enumerate() {
+            if x % CRT_SCALE == CRT_SCALE - 1 || y % CRT_SCALE == CRT_SCALE - 1 {
+                darken(pixel, GRID_LEVEL);
+            }
+        }
+    }
+}
+
+fn bloom(image: &mut Image) {
+    let (width, height) = (image.width, image.height);
+    let source: Vec<f32> = image.pixels.iter().map(|&c| c as f32).collect();
+
+    // Box blur, done horizontally and then vertically.
+    let horizontal = box_blur(&source, width, height, 3, width * 3);
+    let blurred = box_blur(&horizontal, height, width, width * 3, 3);
+
+    for (c, glow) in image.pixels.iter_mut().zip(blurred) {
+        *c = (*c as f32 + glow * BLOOM_STRENGTH).min(255.0) as u8;
+    }
+}
+
+// Averages every value with the ones up to BLOOM_RADIUS steps away along
+// one axis. `step` is the distance between neighbours on that axis and
+// `stride` the distance between lines.
+fn box_blur(source: &[f32], length: usize, lines: usize, step: usize, stride: usize) -> Vec<f32> {
+    let mut blurred = vec![0.0; source.len()];
+    let window = (2 * BLOOM_RADIUS + 1) as f32;
+
+    for line in 0..lines {
+        for channel in 0..3 {
+            let at = |i: usize| source[line * stride + i * step + channel];
+            let mut sum: f32 = (0..=BLOOM_RADIUS.min(length - 1)).map(at).sum();
+
+            for i in 0..length {
+                blurred[line * stride + i * step + channel] = sum / window;
+
+                if i + BLOOM_RADIUS + 1 < length {
+                    sum += at(i + BLOOM_RADIUS + 1);
+                }
+                if i >= BLOOM_RADIUS {
+                    sum -= at(i - BLOOM_RADIUS);
+                }
+            }
+        }
+    }
+
+    blurred
+}
+
+fn curvature(image: &Image) -> Image {
+    let (width, height) = (image.width, image.height);
+    let mut pixels = vec![0; image.pixels.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            // Coordinates from -1 to 1, pushed outwards the further they
+            // are from the centre.
+            let u = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+            let v = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+            let su = u + u * (v / CURVATURE) * (v / CURVATURE);
+            let sv = v + v * (u / CURVATURE) * (u / CURVATURE);
+
+            if su.abs() > 1.0 || sv.abs() > 1.0 {
+                continue;
+            }
+
+            let sx = (((su + 1.0) / 2.0 * width as f32) as usize).min(width - 1);
+            let sy = (((sv + 1.0) / 2.0 * height as f32) as usize).min(height - 1);
+            let (i, j) = ((y * width + x) * 3, (sy * width + sx) * 3);
+            pixels[i..i + 3].copy_from_slice(&image.pixels[j..j + 3]);
+        }
+    }
+
+    Image { width, height, pixels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, CrtFilter, Image, CRT_SCALE};
+
+    fn lit_pixel() -> Image {
+        let mut pixels = vec![0; 3 * 3 * 3];
+        pixels[12..15].copy_from_slice(&[200, 200, 200]);
+        Image { width: 3, height: 3, pixels }
+    }
+
+    fn at(image: &Image, x: usize, y: usize) -> u8 {
+        image.pixels[(y * image.width + x) * 3]
+    }
+
+    #[test]
+    fn test_parse_chain() {
+        assert_eq!(Ok(vec![]), CrtFilter::parse_chain("none"));
+        assert_eq!(
+            Ok(vec![CrtFilter::Grid, CrtFilter::Scanlines, CrtFilter::Bloom, CrtFilter::Curvature]),
+            CrtFilter::parse_chain("grid, crt")
+        );
+        assert!(CrtFilter::parse_chain("scanlines,blur").is_err());
+    }
+
+    #[test]
+    fn test_empty_chain_keeps_image() {
+        let image = apply(&[], lit_pixel());
+        assert_eq!((3, 3), (image.width, image.height));
+        assert_eq!(lit_pixel().pixels, image.pixels);
+    }
+
+    #[test]
+    fn test_scanlines_and_grid() {
+        let image = apply(&[CrtFilter::Scanlines], lit_pixel());
+        assert_eq!((3 * CRT_SCALE, 3 * CRT_SCALE), (image.width, image.height));
+        assert_eq!(200, at(&image, CRT_SCALE, CRT_SCALE));
+        assert_eq!(110, at(&image, CRT_SCALE, 2 * CRT_SCALE - 1));
+
+        let image = apply(&[CrtFilter::Grid], lit_pixel());
+        assert_eq!(200, at(&image, CRT_SCALE, CRT_SCALE));
+        assert_eq!(140, at(&image, 2 * CRT_SCALE - 1, CRT_SCALE));
+    }
+
+    #[test]
+    fn test_bloom_spreads_light() {
+        let image = apply(&[CrtFilter::Bloom], lit_pixel());
+        assert_eq!(0, at(&image, 0, 0));
+        assert!(at(&image, CRT_SCALE - 1, CRT_SCALE + 2) > 0);
+        assert!(at(&image, CRT_SCALE + 2, CRT_SCALE + 2) > 200);
+    }
+
+    #[test]
+    fn test_curvature_blackens_corners() {
+        let image = Image { width: 16, height: 8, pixels: vec![255; 16 * 8 * 3] };
+        let image = apply(&[CrtFilter::Curvature], image);
+        assert_eq!(0, at(&image, 0, 0));
+        assert_eq!(255, at(&image, 0, image.height / 2));
+        assert_eq!(255, at(&image, image.width / 2, image.height / 2));
+    }
+}
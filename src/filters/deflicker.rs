@@ -0,0 +1,245 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::str::FromStr;
+
+use crate::chip8::display::Display;
+use crate::palette::{Palette, Rgb};
+
+// How much of its brightness a pixel keeps on each frame after it has
+// been turned off, when emulating phosphor persistence.
+const PHOSPHOR_DECAY: f32 = 0.55;
+// Below this brightness a pixel is considered completely dark.
+const PHOSPHOR_CUTOFF: f32 = 0.02;
+
+/// CHIP-8 programs erase and redraw sprites with XOR, so moving sprites
+/// are often drawn in one frame and gone in the next one. These modes
+/// reduce the resulting flicker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeflickerMode {
+    /// Every change is shown as soon as the program draws it.
+    None,
+    /// Pixels that are turned off fade out over a few frames, like on a
+    /// CRT with slow phosphor.
+    Phosphor,
+    /// The display is only shown at 60 Hz, and a pixel is lit if it was
+    /// set at any moment since the previous frame.
+    FrameOr,
+}
+
+impl FromStr for DeflickerMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(DeflickerMode::None),
+            "phosphor" => Ok(DeflickerMode::Phosphor),
+            "frame-or" => Ok(DeflickerMode::FrameOr),
+            _ => Err(format!("unknown deflicker mode: {}", s)),
+        }
+    }
+}
+
+/// Turns the contents of the display over time into the frames that
+/// are shown on screen.
+pub(crate) struct Deflicker {
+    mode: DeflickerMode,
+    width: usize,
+    height: usize,
+    /// Every pixel that was set at some point since the last frame.
+    merged: Vec<u8>,
+    /// Whether the program drew anything since the last frame.
+    drawn: bool,
+    /// Pixels of the frame that is on screen.
+    shown: Vec<u8>,
+    /// Brightness of each pixel in phosphor mode, from 0 (background)
+    /// to 1 (fully lit).
+    glow: Vec<f32>,
+}
+
+impl Deflicker {
+    pub fn new(mode: DeflickerMode) -> Self {
+        Deflicker {
+            mode,
+            width: 0,
+            height: 0,
+            merged: Vec::new(),
+            drawn: false,
+            shown: Vec::new(),
+            glow: Vec::new(),
+        }
+    }
+
+    /// Width and height of the frames produced.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Must be called whenever the program draws on the display. Returns
+    /// true if the new frame should be shown right away.
+    pub fn draw(&mut self, display: &Display) -> bool {
+        if self.size() != (display.width(), display.height()) {
+            self.reset(display);
+        }
+
+        match self.mode {
+            DeflickerMode::None => {
+                self.shown.copy_from_slice(display.pixels());
+                true
+            }
+            _ => {
+                self.merged
+                    .iter_mut()
+                    .zip(display.pixels())
+                    .for_each(|(m, &v)| *m |= v);
+                self.drawn = true;
+                false
+            }
+        }
+    }
+
+    /// Must be called 60 times per second. Returns true if the frame
+    /// should be shown.
+    pub fn end_frame(&mut self, display: &Display) -> bool {
+        if self.size() != (display.width(), display.height()) {
+            self.reset(display);
+            return true;
+        }
+
+        let show = match self.mode {
+            DeflickerMode::None => false,
+            DeflickerMode::FrameOr => {
+                self.shown.copy_from_slice(&self.merged);
+                self.drawn
+            }
+            DeflickerMode::Phosphor => {
+                let mut fading = false;
+
+                for ((glow, shown), &v) in self.glow.iter_mut().zip(self.shown.iter_mut()).zip(&self.merged) {
+                    if v != 0 {
+                        *glow = 1.0;
+                        *shown = v;
+                    } else if *glow > 0.0 {
+                        *glow *= PHOSPHOR_DECAY;
+                        if *glow < PHOSPHOR_CUTOFF {
+                            *glow = 0.0;
+                        }
+                        fading = true;
+                    }
+                }
+
+                self.drawn || fading
+            }
+        };
+
+        self.merged.copy_from_slice(display.pixels());
+        self.drawn = false;
+
+        show
+    }
+
+    /// Renders the frame on screen as a packed RGB24 buffer.
+    pub fn to_rgb(&self, palette: &Palette) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.shown.len() * 3);
+
+        match self.mode {
+            DeflickerMode::Phosphor => {
+                let background = palette.background();
+
+                for (&v, &glow) in self.shown.iter().zip(&self.glow) {
+                    let color = blend(background, palette.color(v), glow);
+                    buffer.extend_from_slice(&[color.0, color.1, color.2]);
+                }
+            }
+            _ => {
+                for &v in &self.shown {
+                    let color = palette.color(v);
+                    buffer.extend_from_slice(&[color.0, color.1, color.2]);
+                }
+            }
+        }
+
+        buffer
+    }
+
+    fn reset(&mut self, display: &Display) {
+        self.width = display.width();
+        self.height = display.height();
+        self.merged = display.pixels().to_vec();
+        self.drawn = false;
+        self.shown = display.pixels().to_vec();
+        self.glow = self.shown.iter().map(|&v| if v != 0 { 1.0 } else { 0.0 }).collect();
+    }
+}
+
+fn blend(from: Rgb, to: Rgb, amount: f32) -> Rgb {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    Rgb(mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deflicker, DeflickerMode};
+    use crate::chip8::display::Display;
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_frame_or_merges_draws() {
+        let mut deflicker = Deflicker::new(DeflickerMode::FrameOr);
+        let mut display = Display::default();
+
+        assert!(deflicker.end_frame(&display));
+
+        // A sprite is drawn and erased again within the same frame.
+        display[(2, 0)] = 1;
+        assert!(!deflicker.draw(&display));
+        display[(2, 0)] = 0;
+        display[(5, 0)] = 1;
+        assert!(!deflicker.draw(&display));
+
+        assert!(deflicker.end_frame(&display));
+        let rgb = deflicker.to_rgb(&Palette::default());
+        assert_eq!(&[255, 255, 0], &rgb[6..9]);
+        assert_eq!(&[255, 255, 0], &rgb[15..18]);
+
+        // Nothing was drawn, so there is nothing new to show.
+        assert!(!deflicker.end_frame(&display));
+    }
+
+    #[test]
+    fn test_phosphor_fades_out() {
+        let mut deflicker = Deflicker::new(DeflickerMode::Phosphor);
+        let mut display = Display::default();
+        display[(0, 0)] = 1;
+        deflicker.end_frame(&display);
+
+        display[(0, 0)] = 0;
+        deflicker.draw(&display);
+
+        let mut levels = Vec::new();
+        while deflicker.end_frame(&display) {
+            levels.push(deflicker.to_rgb(&Palette::default())[0]);
+        }
+
+        assert_eq!(vec![255, 140, 77, 42, 23, 13, 7, 0], levels);
+    }
+}
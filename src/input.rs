@@ -0,0 +1,166 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// A keypad button going down or up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct KeyEvent {
+    /// When the host saw it happen.
+    pub time: Instant,
+    pub button: usize,
+    pub pressed: bool,
+}
+
+/// Keypad events waiting to reach the CHIP-8. They are only applied at
+/// frame boundaries, so the program sees the same input at the same
+/// point of its execution no matter when the host delivered it.
+pub(crate) struct InputQueue {
+    events: VecDeque<KeyEvent>,
+    log: Option<Box<dyn Write>>,
+    start: Instant,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue {
+            events: VecDeque::new(),
+            log: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// Writes every event applied to the keypad to `path`, or to the
+    /// standard output if it is `-`. Each line holds the frame it was
+    /// applied at, how long after the start the host saw it, the button
+    /// and whether it went down or up.
+    pub fn log_to(&mut self, path: &Path) -> Result<(), String> {
+        self.log = Some(if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(path).map_err(|e| e.to_string())?))
+        });
+
+        Ok(())
+    }
+
+    pub fn push(&mut self, event: KeyEvent) {
+        self.events.push_back(event);
+    }
+
+    /// Applies the queued events to the keypad at the start of `frame`.
+    /// Each button changes at most once per frame, so taps shorter than
+    /// a frame are not lost: a button that goes down stays down for at
+    /// least one frame, and the events that come after that change wait
+    /// for the next frame.
+    pub fn apply(&mut self, frame: u64, keypad: &mut [bool; 16]) {
+        let mut changed = [false; 16];
+        let mut later = VecDeque::new();
+
+        while let Some(event) = self.events.pop_front() {
+            let button = event.button;
+
+            if changed[button] {
+                later.push_back(event);
+            } else if keypad[button] != event.pressed {
+                keypad[button] = event.pressed;
+                changed[button] = true;
+                self.log(frame, &event);
+            }
+        }
+
+        self.events = later;
+    }
+
+    fn log(&mut self, frame: u64, event: &KeyEvent) {
+        if let Some(log) = self.log.as_mut() {
+            let elapsed = event.time.saturating_duration_since(self.start);
+            let result = writeln!(
+                log,
+                "{} {}ms {:X} {}",
+                frame,
+                elapsed.as_millis(),
+                event.button,
+                if event.pressed { "down" } else { "up" }
+            );
+
+            if let Err(e) = result {
+                eprintln!("Input log stopped: {}", e);
+                self.log = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::{InputQueue, KeyEvent};
+
+    fn event(button: usize, pressed: bool) -> KeyEvent {
+        KeyEvent { time: Instant::now(), button, pressed }
+    }
+
+    #[test]
+    fn test_events_wait_for_frame() {
+        let mut queue = InputQueue::new();
+        let mut keypad = [false; 16];
+
+        queue.push(event(0x5, true));
+        assert!(!keypad[0x5]);
+
+        queue.apply(0, &mut keypad);
+        assert!(keypad[0x5]);
+
+        queue.push(event(0x5, false));
+        queue.push(event(0x6, true));
+        queue.apply(1, &mut keypad);
+        assert!(!keypad[0x5]);
+        assert!(keypad[0x6]);
+    }
+
+    #[test]
+    fn test_short_tap_lasts_a_frame() {
+        let mut queue = InputQueue::new();
+        let mut keypad = [false; 16];
+
+        queue.push(event(0xA, true));
+        queue.push(event(0xA, false));
+        queue.push(event(0xA, true));
+        queue.push(event(0x1, true));
+
+        queue.apply(0, &mut keypad);
+        assert!(keypad[0xA]);
+        assert!(keypad[0x1]);
+
+        queue.apply(1, &mut keypad);
+        assert!(!keypad[0xA]);
+
+        queue.apply(2, &mut keypad);
+        assert!(keypad[0xA]);
+    }
+}
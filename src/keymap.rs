@@ -0,0 +1,142 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+/// Names of the built-in keymaps, which can be picked with `--keymap`.
+pub const PRESETS: &[&str] = &["default", "arrows", "pong"];
+
+/// Host keys bound to each button of the CHIP-8 keypad. A button can have
+/// any number of keys. Keys are physical keys, named after what they are
+/// on a US keyboard the way SDL names them (e.g. "1", "Q", "Left" or
+/// "Keypad 8"), so the layout stays in the same place on AZERTY or Dvorak
+/// keyboards.
+///
+/// Game controller inputs are named `Pad<player> <input>`, where the input
+/// is a button or an axis in SDL's controller mapping format: `Pad1 a`,
+/// `Pad1 dpup`, `Pad2 start`, `Pad1 -lefty` (left stick pushed up) or
+/// `Pad1 +righttrigger`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap([Vec<String>; 16]);
+
+impl Default for Keymap {
+    // +---+---+---+---+      +---+---+---+---+
+    // | 1 | 2 | 3 | C |      | 1 | 2 | 3 | 4 |
+    // +---+---+---+---+      +---+---+---+---+
+    // | 4 | 5 | 6 | D |      | Q | W | E | R |
+    // +---+---+---+---+  =>  +---+---+---+---+
+    // | 7 | 8 | 9 | E |      | A | S | D | F |
+    // +---+---+---+---+      +---+---+---+---+
+    // | A | 0 | B | F |      | Z | X | C | V |
+    // +---+---+---+---+      +---+---+---+---+
+    fn default() -> Self {
+        let keys = [
+            "X", "1", "2", "3",
+            "Q", "W", "E", "A",
+            "S", "D", "Z", "C",
+            "4", "R", "F", "V",
+        ];
+
+        Keymap(keys.map(|k| vec![k.to_string()]))
+    }
+}
+
+impl Keymap {
+    /// Returns one of the built-in keymaps:
+    ///
+    /// * `default` is the keypad layout on the left side of the keyboard.
+    /// * `arrows` adds the arrow keys to the default layout, bound to the
+    ///   5/7/8/9 cluster most games use for movement, and Space to 6. The
+    ///   D-pad and left stick of the first controller work like the arrow
+    ///   keys, and its A button like Space.
+    /// * `pong` adds controllers for two player games where the paddles
+    ///   move with 1/4 and C/D: the first controller drives the left
+    ///   paddle and the second one the right paddle.
+    pub fn preset(name: &str) -> Option<Self> {
+        let mut keymap = Keymap::default();
+
+        match name {
+            "default" => {}
+            "arrows" => {
+                for &(button, key, pad, stick) in &[
+                    (0x5, "Up", "Pad1 dpup", "Pad1 -lefty"),
+                    (0x7, "Left", "Pad1 dpleft", "Pad1 -leftx"),
+                    (0x8, "Down", "Pad1 dpdown", "Pad1 +lefty"),
+                    (0x9, "Right", "Pad1 dpright", "Pad1 +leftx"),
+                ] {
+                    keymap.add(button, key);
+                    keymap.add(button, pad);
+                    keymap.add(button, stick);
+                }
+                keymap.add(0x6, "Space");
+                keymap.add(0x6, "Pad1 a");
+            }
+            "pong" => {
+                for &(button, pad, stick) in &[
+                    (0x1, "Pad1 dpup", "Pad1 -lefty"),
+                    (0x4, "Pad1 dpdown", "Pad1 +lefty"),
+                    (0xC, "Pad2 dpup", "Pad2 -lefty"),
+                    (0xD, "Pad2 dpdown", "Pad2 +lefty"),
+                ] {
+                    keymap.add(button, pad);
+                    keymap.add(button, stick);
+                }
+            }
+            _ => return None,
+        }
+
+        Some(keymap)
+    }
+
+    /// Replaces the keys bound to the keypad button `button` (0x0 to 0xF).
+    pub fn bind(&mut self, button: u8, keys: &[String]) {
+        self.0[button as usize] = keys.to_vec();
+    }
+
+    /// Binds one more key to the keypad button `button`.
+    pub fn add(&mut self, button: u8, key: &str) {
+        self.0[button as usize].push(key.to_string());
+    }
+
+    /// Returns the keys bound to the keypad button `button`.
+    pub fn keys(&self, button: u8) -> &[String] {
+        &self.0[button as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Keymap, PRESETS};
+
+    #[test]
+    fn test_presets() {
+        for name in PRESETS {
+            assert!(Keymap::preset(name).is_some());
+        }
+        assert_eq!(None, Keymap::preset("azerty"));
+
+        let arrows = Keymap::preset("arrows").unwrap();
+        assert_eq!(["W", "Up", "Pad1 dpup", "Pad1 -lefty"], arrows.keys(0x5));
+        assert_eq!(["X"], arrows.keys(0x0));
+
+        let pong = Keymap::preset("pong").unwrap();
+        assert_eq!(["4", "Pad2 dpup", "Pad2 -lefty"], pong.keys(0xC));
+    }
+}
@@ -0,0 +1,208 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8;
+use crate::chip8::display::Display;
+use crate::emulator::{Emulator, Options};
+use crate::metadata::Metadata;
+use crate::rom;
+
+/// Directory the launcher lists when none is given.
+pub const DEFAULT_GAMES_DIR: &str = "games";
+// Number of frames run to make the preview of a ROM. Most games show
+// their title screen by then.
+const PREVIEW_FRAMES: usize = 120;
+
+/// A ROM listed in the launcher.
+pub(crate) struct Entry {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    /// What the sidecar of the ROM says about it, if it has one.
+    pub metadata: Option<Metadata>,
+    /// What the display shows after `PREVIEW_FRAMES` frames, once it has
+    /// been worked out. `None` inside means the ROM stopped with an error before.
+    preview: Option<Option<Display>>,
+}
+
+/// List of ROMs to pick from, with the selected one previewed.
+pub(crate) struct Launcher {
+    entries: Vec<Entry>,
+    selected: usize,
+}
+
+impl Launcher {
+    /// Lists the files in `dir`, sorted by name. Hidden files, sidecars
+    /// and directories are left out.
+    pub fn open(dir: &Path, options: &Options) -> Result<Self, String> {
+        let read_dir = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        let mut entries = Vec::new();
+
+        for entry in read_dir.filter_map(Result::ok) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+            match entry.metadata() {
+                Ok(file) if file.is_file() && !name.starts_with('.') && !Metadata::is_sidecar(&path) => {
+                    // A broken sidecar should not keep the ROM from being
+                    // listed.
+                    let metadata = fs::read(&path)
+                        .map_err(|e| format!("{}: {}", path.display(), e))
+                        .and_then(|data| Metadata::find(&path, &data))
+                        .unwrap_or_else(|e| {
+                            eprintln!("{}", e);
+                            None
+                        });
+                    entries.push(Entry { path, name, size: file.len(), metadata, preview: None });
+                }
+                _ => {}
+            }
+        }
+
+        if entries.is_empty() {
+            return Err(format!("no ROMs in {}", dir.display()));
+        }
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut launcher = Launcher { entries, selected: 0 };
+        launcher.select(0, options);
+        Ok(launcher)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> &Entry {
+        &self.entries[self.selected]
+    }
+
+    pub fn selected_index(&self) -> usize {
+        self.selected
+    }
+
+    /// Moves the selection by `offset` entries, wrapping around the ends
+    /// of the list.
+    pub fn move_by(&mut self, offset: isize, options: &Options) {
+        let len = self.entries.len() as isize;
+        let index = (self.selected as isize + offset).rem_euclid(len);
+        self.select(index as usize, options);
+    }
+
+    fn select(&mut self, index: usize, options: &Options) {
+        self.selected = index;
+
+        let entry = &mut self.entries[index];
+        if entry.preview.is_none() {
+            entry.preview = Some(preview(&entry.path, options));
+        }
+    }
+
+    /// Preview of the selected ROM, if it could be made.
+    pub fn preview(&self) -> Option<&Display> {
+        self.selected().preview.as_ref().and_then(Option::as_ref)
+    }
+
+    /// Lines of text describing the selected ROM, which may be too long
+    /// to fit and need wrapping.
+    pub fn details(&self) -> Vec<String> {
+        let entry = self.selected();
+        let metadata = match &entry.metadata {
+            Some(metadata) => metadata,
+            None => return vec![entry.name.clone(), format!("{} bytes", entry.size)],
+        };
+
+        let mut lines = vec![metadata.title.clone().unwrap_or_else(|| entry.name.clone())];
+        let mut about = vec![format!("{} bytes", entry.size)];
+        about.extend(metadata.platform.clone());
+        lines.push(about.join("  "));
+        lines.extend(metadata.author.as_ref().map(|author| format!("By {}", author)));
+        lines.push(String::new());
+        lines.extend(metadata.description.clone());
+        lines.extend(metadata.key_hint_lines());
+        lines
+    }
+}
+
+/// Runs a ROM without any input for `PREVIEW_FRAMES` frames, and returns
+/// the display. Broken ROMs can stop with an error before, in which case
+/// there is no preview.
+fn preview(path: &Path, options: &Options) -> Option<Display> {
+    // Each ROM is previewed with its own options.
+    let (rom, options) = rom::open(path, options).ok()?;
+
+    let mut chip8 = chip8::new_chip8(&rom, &options.platform, options.quirks);
+    for _ in 0..PREVIEW_FRAMES {
+        Emulator::run_frame(&mut chip8, options.instructions_per_frame, |_| {}).ok()?;
+    }
+    Some(chip8.display)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{preview, Launcher};
+    use crate::emulator::Options;
+
+    #[test]
+    fn test_launcher() {
+        let dir = std::env::temp_dir().join(format!("chippy-launcher-{}", std::process::id()));
+        fs::create_dir_all(dir.join("subdirectory")).unwrap();
+        // Clears the screen and draws the 0 font sprite at (0, 0) forever.
+        fs::write(dir.join("ZERO"), [0x00, 0xE0, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x06]).unwrap();
+        fs::write(dir.join("EMPTY"), []).unwrap();
+        fs::write(dir.join(".hidden"), [0x12, 0x00]).unwrap();
+        fs::write(dir.join("ZERO.chip8.toml"), "title = \"Zero\"\n[key-hints]\n5 = \"Nothing\"").unwrap();
+
+        let options = Options::default();
+        let mut launcher = Launcher::open(&dir, &options).unwrap();
+        let names: Vec<&str> = launcher.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(vec!["EMPTY", "ZERO"], names);
+        assert_eq!(vec!["EMPTY".to_string(), "0 bytes".to_string()], launcher.details());
+
+        launcher.move_by(-1, &options);
+        assert_eq!("ZERO", launcher.selected().name);
+        assert_eq!(vec!["Zero", "8 bytes", "", "5: Nothing"], launcher.details());
+        let preview = launcher.preview().unwrap();
+        assert_eq!(1, preview[(0, 0)]);
+        assert_eq!(0, preview[(1, 1)]);
+
+        launcher.move_by(1, &options);
+        assert_eq!(0, launcher.selected_index());
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(Launcher::open(&dir, &options).is_err());
+    }
+
+    #[test]
+    fn test_broken_preview() {
+        let path = std::env::temp_dir().join(format!("chippy-preview-{}", std::process::id()));
+        // Returns from a subroutine that was never called.
+        fs::write(&path, [0x00, 0xEE]).unwrap();
+        assert!(preview(&path, &Options::default()).is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
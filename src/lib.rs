@@ -20,6 +20,20 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod config;
 pub mod emulator;
+pub mod filters;
 mod chip8;
 mod drivers;
+mod input;
+mod launcher;
+pub mod keymap;
+pub mod metadata;
+pub mod octo;
+pub mod octocart;
+pub mod palette;
+mod recorder;
+pub mod rom;
+pub mod sound;
+mod watch;
+mod wav;
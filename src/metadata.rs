@@ -0,0 +1,256 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Metadata sidecars: TOML files kept next to a ROM that describe it and
+//! hold the settings it needs. The sidecar of `games/BRIX` is
+//! `games/BRIX.chip8.toml`, and the one of `pong.ch8` is `pong.chip8.toml`.
+//! A sidecar can also give the SHA-1 of its ROM, so that it is still found
+//! once the ROM is renamed, as long as both stay in the same directory.
+//!
+//! ```toml
+//! title = "Brix"
+//! author = "Andreas Gustafsson"
+//! description = "Break all the bricks with the ball."
+//! platform = "chip-8"
+//! sha1 = "f13766c14aeb02ad8d4d103cb5eadd282d20cddc"
+//!
+//! [key-hints]
+//! 4 = "Left"
+//! 6 = "Right"
+//!
+//! [emulation]
+//! instructions-per-frame = 10
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::config::{Config, EmulationConfig, QuirksConfig, VideoConfig};
+
+/// Extension of sidecar files, which replaces the one of the ROM.
+pub const EXTENSION: &str = "chip8.toml";
+
+/// What a sidecar says about a ROM. Everything is optional.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// Platform the program was written for, one of the presets.
+    pub platform: Option<String>,
+    /// SHA-1 of the ROM, in hexadecimal.
+    pub sha1: Option<String>,
+    /// What the keypad buttons do, keyed by the button as a hexadecimal
+    /// digit.
+    pub key_hints: BTreeMap<String, String>,
+    /// Palette and other display settings.
+    pub video: VideoConfig,
+    pub emulation: EmulationConfig,
+    pub quirks: QuirksConfig,
+}
+
+impl Metadata {
+    /// Location of the sidecar of the ROM at `rom`.
+    pub fn path(rom: &Path) -> PathBuf {
+        rom.with_extension(EXTENSION)
+    }
+
+    /// Tells whether `path` is a sidecar rather than a ROM.
+    pub fn is_sidecar(path: &Path) -> bool {
+        path.to_str().is_some_and(|p| p.ends_with(&format!(".{}", EXTENSION)))
+    }
+
+    /// Reads the sidecar of the ROM at `rom`, whose contents are `data`,
+    /// if it has one: the sidecar named after the ROM, or else one next to
+    /// it whose SHA-1 is the one of `data`.
+    pub fn find(rom: &Path, data: &[u8]) -> Result<Option<Self>, String> {
+        if let Some(metadata) = Self::read(&Self::path(rom))? {
+            return Ok(Some(metadata));
+        }
+
+        let dir = match rom.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+        let mut sidecars: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(Result::ok).map(|e| e.path()).filter(|p| Self::is_sidecar(p)).collect(),
+            Err(_) => return Ok(None),
+        };
+        sidecars.sort();
+
+        // The sidecars of other ROMs being broken is not a problem for
+        // this one.
+        let hash = sha1(data);
+        Ok(sidecars
+            .iter()
+            .filter_map(|path| Self::read(path).ok().flatten())
+            .find(|metadata| metadata.sha1.as_ref().is_some_and(|h| h.eq_ignore_ascii_case(&hash))))
+    }
+
+    // Reads the sidecar at `path`, if there is one.
+    fn read(path: &Path) -> Result<Option<Self>, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let metadata: Self = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+        for button in metadata.key_hints.keys() {
+            match u8::from_str_radix(button, 16) {
+                Ok(_) if button.len() == 1 => {}
+                _ => return Err(format!("invalid keypad button: {}", button)),
+            }
+        }
+
+        if let Some(hash) = &metadata.sha1 {
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("invalid SHA-1: {}", hash));
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// The settings of the sidecar, to go on top of the global ones.
+    pub fn config(&self) -> Config {
+        Config {
+            video: self.video.clone(),
+            emulation: EmulationConfig {
+                platform: self.platform.clone().or_else(|| self.emulation.platform.clone()),
+                ..self.emulation.clone()
+            },
+            quirks: self.quirks.clone(),
+            ..Config::default()
+        }
+    }
+
+    /// Key hints as text, such as `4: Left`, in the order of the buttons.
+    pub fn key_hint_lines(&self) -> Vec<String> {
+        let mut hints: Vec<(u8, &String)> = self
+            .key_hints
+            .iter()
+            .filter_map(|(button, hint)| u8::from_str_radix(button, 16).ok().map(|b| (b, hint)))
+            .collect();
+        hints.sort();
+
+        hints.iter().map(|(button, hint)| format!("{:X}: {}", button, hint)).collect()
+    }
+}
+
+/// SHA-1 of `data`, in lowercase hexadecimal.
+pub fn sha1(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::{sha1, Metadata};
+    use crate::emulator::Options;
+    use crate::palette::Rgb;
+
+    #[test]
+    fn test_metadata() {
+        let metadata = Metadata::parse(r##"
+            title = "Brix"
+            platform = "chip-8"
+
+            [key-hints]
+            6 = "Right"
+            4 = "Left"
+            a = "Pause"
+
+            [video]
+            background = "#112233"
+
+            [emulation]
+            instructions-per-frame = 7
+
+            [quirks]
+            clip = true
+        "##).unwrap();
+
+        assert_eq!(Some("Brix"), metadata.title.as_deref());
+        assert_eq!(vec!["4: Left", "6: Right", "A: Pause"], metadata.key_hint_lines());
+
+        let mut options = Options::default();
+        metadata.config().apply(&mut options, None).unwrap();
+        assert_eq!(Rgb(0x11, 0x22, 0x33), options.palette.colors[0]);
+        assert_eq!(7, options.instructions_per_frame);
+        assert!(options.quirks.clip);
+
+        assert!(Metadata::parse("[key-hints]\n10 = \"Up\"").is_err());
+        assert!(Metadata::parse("year = 1990").is_err());
+        assert!(Metadata::parse("sha1 = \"f13766c1\"").is_err());
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(Path::new("games/BRIX.chip8.toml"), Metadata::path(Path::new("games/BRIX")));
+        assert_eq!(Path::new("pong.chip8.toml"), Metadata::path(Path::new("pong.ch8")));
+        assert!(Metadata::is_sidecar(Path::new("games/BRIX.chip8.toml")));
+        assert!(!Metadata::is_sidecar(Path::new("games/BRIX")));
+
+        // Every game that comes with the emulator has a valid sidecar,
+        // with the right hash.
+        for entry in fs::read_dir("games").unwrap() {
+            let path = entry.unwrap().path();
+            if !Metadata::is_sidecar(&path) {
+                let data = fs::read(&path).unwrap();
+                let metadata = Metadata::find(&path, &data).unwrap().unwrap();
+                assert!(metadata.title.is_some(), "{}", path.display());
+                assert_eq!(Some(sha1(&data)), metadata.sha1, "{}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn test_find_by_hash() {
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", sha1(b"abc"));
+
+        let dir = std::env::temp_dir().join(format!("chippy-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("Brix (renamed).ch8"), b"abc").unwrap();
+        fs::write(dir.join("BRIX.chip8.toml"), "title = \"Brix\"\nsha1 = \"A9993E364706816ABA3E25717850C26C9CD0D89D\"").unwrap();
+        fs::write(dir.join("BROKEN.chip8.toml"), "year = 1990").unwrap();
+
+        // A renamed ROM is recognised by its contents.
+        let metadata = Metadata::find(&dir.join("Brix (renamed).ch8"), b"abc").unwrap();
+        assert_eq!(Some("Brix"), metadata.unwrap().title.as_deref());
+        assert_eq!(None, Metadata::find(&dir.join("OTHER"), b"abd").unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1056 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Assembler for Octo, the language of the Octo CHIP-8 environment, in
+//! which the programs of Octo cartridges are written.
+//!
+//! ```text
+//! : main
+//!   i := smile
+//!   loop
+//!     sprite v0 v1 4
+//!     v0 += 1
+//!   again
+//!
+//! : smile
+//!   0x24 0x00 0x81 0x7E
+//! ```
+//!
+//! Labels, constants, aliases, macros, `:calc` expressions and the
+//! control structures (`if`, `loop`, `while`) are supported, as well as
+//! the SUPER-CHIP and XO-CHIP instructions. `:stringmode` is not.
+
+use std::collections::HashMap;
+
+/// Octo programs always start at 0x200.
+const START: usize = 0x200;
+/// Largest address Octo programs can use, XO-CHIP memory included.
+const MAX_ADDRESS: usize = 0xFFFF;
+/// How many macros can be expanded, so that one that expands into itself
+/// is an error rather than a hang.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Assembles an Octo program into a binary loaded at 0x200.
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut assembler = Assembler::new(tokenize(source)?);
+    assembler.run().map_err(|e| format!("line {}: {}", assembler.line, e))?;
+    assembler.finish()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Token {
+    text: String,
+    /// Whether the token is a string literal rather than a word.
+    string: bool,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '#' {
+                break;
+            } else if c == '"' {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => text.push('\n'),
+                            Some('t') => text.push('\t'),
+                            Some(c) => text.push(c),
+                            None => return Err(format!("line {}: unterminated string", i + 1)),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Err(format!("line {}: unterminated string", i + 1)),
+                    }
+                }
+                tokens.push(Token { text, string: true, line: i + 1 });
+            } else {
+                let mut text = String::new();
+                while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    text.push(c);
+                    chars.next();
+                }
+                tokens.push(Token { text, string: false, line: i + 1 });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// How a reference to a label is patched once its address is known.
+#[derive(Clone, Copy, Debug)]
+enum Patch {
+    /// The lowest 12 bits of the instruction, e.g. `jump label`.
+    Address,
+    /// A whole 16-bit word, e.g. `i := long label`.
+    Long,
+    /// The `:unpack n label` pair, whose first byte keeps `n`.
+    Unpack,
+    /// The `:unpack long label` pair.
+    UnpackLong,
+}
+
+/// Open `if ... begin` blocks and loops.
+#[derive(Clone, Copy, Debug)]
+enum Block {
+    /// Holds the address of the jump to `else` or `end`.
+    Begin(usize),
+    /// Holds the address of the jump to `end`.
+    Else(usize),
+    /// Holds the address the loop starts at.
+    Loop(usize),
+    /// Holds the address of the jump out of the loop.
+    While(usize),
+}
+
+/// A value that may be a label that is not defined yet.
+enum Value {
+    Number(f64),
+    Label(String),
+}
+
+/// Condition of an `if` or a `while`, which skips the next instruction.
+struct Condition {
+    register: u8,
+    operator: String,
+    operand: Operand,
+}
+
+enum Operand {
+    Register(u8),
+    Byte(u8),
+    None,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+struct Assembler {
+    /// Tokens left to read, the next one last.
+    tokens: Vec<Token>,
+    /// Line of the last token read, for errors.
+    line: usize,
+    rom: Vec<u8>,
+    here: usize,
+    /// Whether `: main` comes after something else, so a jump to it has
+    /// to be put at 0x200.
+    jump_to_main: bool,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    /// References to labels, patched once they are all known.
+    patches: Vec<(String, usize, Patch, usize)>,
+    blocks: Vec<(Block, usize)>,
+}
+
+impl Assembler {
+    fn new(mut tokens: Vec<Token>) -> Self {
+        tokens.reverse();
+
+        let aliases = [("compare-temp", 0xF), ("unpack-hi", 0x0), ("unpack-lo", 0x1)];
+
+        Assembler {
+            tokens,
+            line: 0,
+            // Room for the jump to main, which is left out if main comes
+            // first.
+            rom: vec![0, 0],
+            here: START + 2,
+            jump_to_main: false,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: aliases.iter().map(|&(name, v)| (name.to_string(), v)).collect(),
+            macros: HashMap::new(),
+            expansions: 0,
+            patches: Vec::new(),
+            blocks: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the references to labels and returns the binary.
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        if let Some(&(_, line)) = self.blocks.last() {
+            return Err(format!("line {}: this block is never closed", line));
+        }
+
+        if !self.labels.contains_key("main") {
+            return Err("the program has no main label".to_string());
+        }
+        if self.jump_to_main {
+            self.patches.push(("main".to_string(), START, Patch::Address, 0));
+            self.rom[0] = 0x10;
+        }
+
+        for (name, at, patch, line) in std::mem::take(&mut self.patches) {
+            let address = *self
+                .labels
+                .get(&name)
+                .ok_or_else(|| format!("line {}: undefined name: {}", line, name))?;
+            let i = at - START;
+
+            match patch {
+                Patch::Address if address > 0xFFF => {
+                    return Err(format!("line {}: {} is at {:#X}, out of reach of this instruction", line, name, address))
+                }
+                Patch::Address => {
+                    self.rom[i] |= (address >> 8) as u8;
+                    self.rom[i + 1] = address as u8;
+                }
+                Patch::Long => {
+                    self.rom[i] = (address >> 8) as u8;
+                    self.rom[i + 1] = address as u8;
+                }
+                Patch::Unpack => {
+                    self.rom[i + 1] |= (address >> 8 & 0xF) as u8;
+                    self.rom[i + 3] = address as u8;
+                }
+                Patch::UnpackLong => {
+                    self.rom[i + 1] = (address >> 8) as u8;
+                    self.rom[i + 3] = address as u8;
+                }
+            }
+        }
+
+        Ok(self.rom)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.pop().ok_or("unexpected end of the program")?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        let token = self.next()?;
+        if token.string {
+            return Err(format!("unexpected string \"{}\"", token.text));
+        }
+        Ok(token.text)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.word()? {
+            ref word if word == expected => Ok(()),
+            word => Err(format!("expected {}, found {}", expected, word)),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().filter(|t| !t.string).map(|t| t.text.as_str())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.here > MAX_ADDRESS {
+            return Err("the program does not fit in memory".to_string());
+        }
+
+        let i = self.here - START;
+        if i >= self.rom.len() {
+            self.rom.resize(i + 1, 0);
+        }
+        self.rom[i] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), String> {
+        self.emit((opcode >> 8) as u8)?;
+        self.emit(opcode as u8)
+    }
+
+    /// Emits an instruction that takes an address in its lowest 12 bits.
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let at = self.here;
+        match self.value()? {
+            Value::Number(n) => self.instruction(opcode | integer(n, 0, 0xFFF, "address")? as u16),
+            Value::Label(name) => {
+                self.patches.push((name, at, Patch::Address, self.line));
+                self.instruction(opcode)
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        if token.string {
+            return Err(format!("unexpected string \"{}\"", token.text));
+        }
+        let word = token.text;
+
+        if let Some(register) = self.register_named(&word) {
+            return self.register_statement(register);
+        }
+
+        match word.as_str() {
+            ":" => {
+                let name = self.word()?;
+                if name == "main" {
+                    if self.here == START + 2 && self.rom.len() == 2 {
+                        self.rom.clear();
+                        self.here = START;
+                    } else {
+                        self.jump_to_main = true;
+                    }
+                }
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.word()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.word()?;
+                let value = self.number()?;
+                self.constants.insert(name, value);
+            }
+            ":calc" => {
+                let name = self.word()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+            }
+            ":alias" => {
+                let name = self.word()?;
+                let word = self.word()?;
+                let register = self.register_named(&word).ok_or_else(|| format!("{} is not a register", word))?;
+                self.aliases.insert(name, register);
+            }
+            ":unpack" => {
+                let long = self.peek() == Some("long");
+                let high = if long {
+                    self.next()?;
+                    0
+                } else {
+                    integer(self.number()?, 0, 0xF, "nibble")? as u8
+                };
+
+                let at = self.here;
+                let address = match self.value()? {
+                    Value::Number(n) => integer(n, 0, if long { 0xFFFF } else { 0xFFF }, "address")? as usize,
+                    Value::Label(name) => {
+                        let patch = if long { Patch::UnpackLong } else { Patch::Unpack };
+                        self.patches.push((name, at, patch, self.line));
+                        0
+                    }
+                };
+
+                let (hi, lo) = (self.alias("unpack-hi"), self.alias("unpack-lo"));
+                let first = if long { (address >> 8) as u8 } else { high << 4 | (address >> 8) as u8 };
+                self.instruction(0x6000 | u16::from(hi) << 8 | u16::from(first))?;
+                self.instruction(0x6000 | u16::from(lo) << 8 | (address & 0xFF) as u16)?;
+            }
+            ":org" => {
+                self.here = integer(self.number()?, START as i64, MAX_ADDRESS as i64, "address")? as usize;
+            }
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit(byte)?;
+            }
+            ":pointer" => {
+                let at = self.here;
+                match self.value()? {
+                    Value::Number(n) => self.instruction(integer(n, 0, 0xFFFF, "address")? as u16)?,
+                    Value::Label(name) => {
+                        self.patches.push((name, at, Patch::Long, self.line));
+                        self.instruction(0)?;
+                    }
+                }
+            }
+            ":call" => self.address_instruction(0x2000)?,
+            ":macro" => self.define_macro()?,
+            ":assert" => {
+                let message = match self.tokens.last() {
+                    Some(t) if t.string => Some(self.next()?.text),
+                    _ => None,
+                };
+                self.expect("{")?;
+                if self.calc()? == 0.0 {
+                    return Err(message.unwrap_or_else(|| "assertion failed".to_string()));
+                }
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+            ":stringmode" => return Err(":stringmode is not supported".to_string()),
+
+            ";" | "return" => self.instruction(0x00EE)?,
+            "clear" => self.instruction(0x00E0)?,
+            "hires" => self.instruction(0x00FF)?,
+            "lores" => self.instruction(0x00FE)?,
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(0x00D0 | n)?;
+            }
+            "scroll-right" => self.instruction(0x00FB)?,
+            "scroll-left" => self.instruction(0x00FC)?,
+            "exit" => self.instruction(0x00FD)?,
+            "audio" => self.instruction(0xF002)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(0xF001 | n << 8)?;
+            }
+            "bcd" => self.register_instruction(0xF033)?,
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let opcode = if word == "save" { 0x5002 } else { 0x5003 };
+                    self.instruction(opcode | u16::from(x) << 8 | u16::from(y) << 4)?;
+                } else {
+                    let opcode = if word == "save" { 0xF055 } else { 0xF065 };
+                    self.instruction(opcode | u16::from(x) << 8)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(0xD000 | u16::from(x) << 8 | u16::from(y) << 4 | n)?;
+            }
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xB000)?,
+            "native" => self.address_instruction(0x0000)?,
+            "i" => self.i_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let opcode = match word.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.register_instruction(opcode)?;
+            }
+
+            "if" => {
+                let condition = self.condition()?;
+                match self.word()?.as_str() {
+                    "then" => self.skip_unless(&condition, false)?,
+                    "begin" => {
+                        self.skip_unless(&condition, true)?;
+                        self.blocks.push((Block::Begin(self.here), self.line));
+                        self.instruction(0x1000)?;
+                    }
+                    word => return Err(format!("expected then or begin, found {}", word)),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::Begin(at), _)) => {
+                    self.blocks.push((Block::Else(self.here), self.line));
+                    self.instruction(0x1000)?;
+                    self.patch_jump(at, self.here);
+                }
+                _ => return Err("else without if ... begin".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::Begin(at), _)) | Some((Block::Else(at), _)) => self.patch_jump(at, self.here),
+                _ => return Err("end without if ... begin".to_string()),
+            },
+            "loop" => self.blocks.push((Block::Loop(self.here), self.line)),
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_unless(&condition, true)?;
+                if !self.blocks.iter().any(|(b, _)| matches!(b, Block::Loop(_))) {
+                    return Err("while outside of a loop".to_string());
+                }
+                self.blocks.push((Block::While(self.here), self.line));
+                self.instruction(0x1000)?;
+            }
+            "again" => {
+                let mut exits = Vec::new();
+                let start = loop {
+                    match self.blocks.pop() {
+                        Some((Block::While(at), _)) => exits.push(at),
+                        Some((Block::Loop(start), _)) => break start,
+                        _ => return Err("again without loop".to_string()),
+                    }
+                };
+
+                self.instruction(0x1000 | start as u16)?;
+                for at in exits {
+                    self.patch_jump(at, self.here);
+                }
+            }
+
+            _ if self.macros.contains_key(&word) => self.expand(&word)?,
+            _ if self.constants.contains_key(&word) || parse_number(&word).is_some() => {
+                let byte = byte(self.constants.get(&word).copied().or_else(|| parse_number(&word)).unwrap())?;
+                self.emit(byte)?;
+            }
+            // Anything else is a call to a subroutine.
+            _ => {
+                self.tokens.push(Token { text: word, string: false, line: self.line });
+                self.address_instruction(0x2000)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let operator = self.word()?;
+        let vx = u16::from(x) << 8;
+
+        let register_operation = |n: u16| -> Option<u16> {
+            match n {
+                0x0..=0x7 | 0xE => Some(0x8000 | vx | n),
+                _ => None,
+            }
+        };
+
+        let opcode = match operator.as_str() {
+            ":=" => match self.peek() {
+                Some("random") => {
+                    self.next()?;
+                    0xC000 | vx | u16::from(self.byte()?)
+                }
+                Some("key") => {
+                    self.next()?;
+                    0xF00A | vx
+                }
+                Some("delay") => {
+                    self.next()?;
+                    0xF007 | vx
+                }
+                Some(word) if self.register_named(word).is_some() => {
+                    register_operation(0x0).unwrap() | u16::from(self.register()?) << 4
+                }
+                _ => 0x6000 | vx | u16::from(self.byte()?),
+            },
+            "+=" | "-=" if self.peek().map_or(true, |w| self.register_named(w).is_none()) => {
+                let byte = self.byte()?;
+                let byte = if operator == "-=" { byte.wrapping_neg() } else { byte };
+                0x7000 | vx | u16::from(byte)
+            }
+            _ => {
+                let n = match operator.as_str() {
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(format!("unknown operator: {}", operator)),
+                };
+                register_operation(n).unwrap() | u16::from(self.register()?) << 4
+            }
+        };
+
+        self.instruction(opcode)
+    }
+
+    fn i_statement(&mut self) -> Result<(), String> {
+        match self.word()?.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_instruction(0xF029)
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_instruction(0xF030)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.instruction(0xF000)?;
+                    let at = self.here;
+                    match self.value()? {
+                        Value::Number(n) => self.instruction(integer(n, 0, 0xFFFF, "address")? as u16),
+                        Value::Label(name) => {
+                            self.patches.push((name, at, Patch::Long, self.line));
+                            self.instruction(0)
+                        }
+                    }
+                }
+                _ => self.address_instruction(0xA000),
+            },
+            "+=" => self.register_instruction(0xF01E),
+            operator => Err(format!("unknown operator: {}", operator)),
+        }
+    }
+
+    /// Emits an `Fx..` style instruction whose register comes next.
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), String> {
+        let x = self.register()?;
+        self.instruction(opcode | u16::from(x) << 8)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let register = self.register()?;
+        let operator = self.word()?;
+
+        let operand = match operator.as_str() {
+            "key" | "-key" => Operand::None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => match self.peek() {
+                Some(word) if self.register_named(word).is_some() => Operand::Register(self.register()?),
+                _ => Operand::Byte(self.byte()?),
+            },
+            _ => return Err(format!("unknown comparison: {}", operator)),
+        };
+
+        Ok(Condition { register, operator, operand })
+    }
+
+    /// Emits the instructions that skip the next one unless `condition`
+    /// holds, or if it holds when `negated` is true.
+    fn skip_unless(&mut self, condition: &Condition, negated: bool) -> Result<(), String> {
+        let operator = if negated {
+            match condition.operator.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                _ => "<",
+            }
+        } else {
+            condition.operator.as_str()
+        };
+
+        let vx = u16::from(condition.register) << 8;
+        let temp = u16::from(self.alias("compare-temp")) << 8;
+
+        match (operator, &condition.operand) {
+            ("==", &Operand::Register(y)) => self.instruction(0x9000 | vx | u16::from(y) << 4),
+            ("==", &Operand::Byte(n)) => self.instruction(0x4000 | vx | u16::from(n)),
+            ("!=", &Operand::Register(y)) => self.instruction(0x5000 | vx | u16::from(y) << 4),
+            ("!=", &Operand::Byte(n)) => self.instruction(0x3000 | vx | u16::from(n)),
+            ("key", _) => self.instruction(0xE0A1 | vx),
+            ("-key", _) => self.instruction(0xE09E | vx),
+            // The other comparisons subtract the two values in the
+            // compare-temp register (vF by default), and look at the flag.
+            (_, operand) => {
+                match *operand {
+                    Operand::Register(y) => self.instruction(0x8000 | temp | u16::from(y) << 4)?,
+                    Operand::Byte(n) => self.instruction(0x6000 | temp | u16::from(n))?,
+                    Operand::None => unreachable!(),
+                }
+
+                let x = u16::from(condition.register) << 4;
+                match operator {
+                    ">" => {
+                        self.instruction(0x8005 | temp | x)?;
+                        self.instruction(0x4000 | temp)
+                    }
+                    "<" => {
+                        self.instruction(0x8007 | temp | x)?;
+                        self.instruction(0x4000 | temp)
+                    }
+                    ">=" => {
+                        self.instruction(0x8007 | temp | x)?;
+                        self.instruction(0x3000 | temp)
+                    }
+                    _ => {
+                        self.instruction(0x8005 | temp | x)?;
+                        self.instruction(0x3000 | temp)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Points the jump at `at` to `target`.
+    fn patch_jump(&mut self, at: usize, target: usize) {
+        let i = at - START;
+        self.rom[i] = 0x10 | (target >> 8) as u8;
+        self.rom[i + 1] = target as u8;
+    }
+
+    fn define_label(&mut self, name: String, address: usize) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("{} is already defined", name));
+        }
+        self.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.word()?;
+        let mut arguments = Vec::new();
+        loop {
+            match self.word()? {
+                ref word if word == "{" => break,
+                word => arguments.push(word),
+            }
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            if !token.string {
+                match token.text.as_str() {
+                    "{" => depth += 1,
+                    "}" => depth -= 1,
+                    _ => (),
+                }
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("too many macro expansions, {} may expand into itself", name));
+        }
+
+        let count = self.macros[name].arguments.len();
+        let mut values = Vec::new();
+        for _ in 0..count {
+            values.push(self.next()?);
+        }
+
+        let line = self.line;
+        let m = &self.macros[name];
+        let body: Vec<Token> = m
+            .body
+            .iter()
+            .map(|token| match m.arguments.iter().position(|a| !token.string && *a == token.text) {
+                Some(i) => Token { line, ..values[i].clone() },
+                None => Token { line, ..token.clone() },
+            })
+            .collect();
+
+        self.tokens.extend(body.into_iter().rev());
+        Ok(())
+    }
+
+    fn alias(&self, name: &str) -> u8 {
+        self.aliases[name]
+    }
+
+    fn register_named(&self, word: &str) -> Option<u8> {
+        match word.strip_prefix('v').or_else(|| word.strip_prefix('V')) {
+            Some(n) if n.len() == 1 => u8::from_str_radix(n, 16).ok(),
+            _ => self.aliases.get(word).copied(),
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let word = self.word()?;
+        self.register_named(&word).ok_or_else(|| format!("{} is not a register", word))
+    }
+
+    /// Reads a number, a constant, a label or a `{ ... }` expression.
+    fn value(&mut self) -> Result<Value, String> {
+        let word = self.word()?;
+
+        if word == "{" {
+            return self.calc().map(Value::Number);
+        }
+
+        match parse_number(&word).or_else(|| self.constants.get(&word).copied()) {
+            Some(n) => Ok(Value::Number(n)),
+            None => match self.labels.get(&word) {
+                Some(&address) => Ok(Value::Number(address as f64)),
+                None if self.register_named(&word).is_some() || is_keyword(&word) => {
+                    Err(format!("expected a value, found {}", word))
+                }
+                None => Ok(Value::Label(word)),
+            },
+        }
+    }
+
+    /// Reads a value that has to be known already.
+    fn number(&mut self) -> Result<f64, String> {
+        match self.value()? {
+            Value::Number(n) => Ok(n),
+            Value::Label(name) => Err(format!("undefined name: {}", name)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        self.number().and_then(byte)
+    }
+
+    fn nibble(&mut self) -> Result<u16, String> {
+        integer(self.number()?, 0, 0xF, "nibble").map(|n| n as u16)
+    }
+
+    /// Evaluates an expression up to the closing brace. As in Octo,
+    /// operators have no precedence and are applied from right to left.
+    fn calc(&mut self) -> Result<f64, String> {
+        let value = self.calc_expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn calc_expression(&mut self) -> Result<f64, String> {
+        let left = self.calc_term()?;
+
+        let operator = match self.peek() {
+            Some(word) if is_binary_operator(word) => self.word()?,
+            _ => return Ok(left),
+        };
+        let right = self.calc_expression()?;
+
+        let (a, b) = (left as i64, right as i64);
+        let truth = |t: bool| if t { 1.0 } else { 0.0 };
+
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => (a << b) as f64,
+            ">>" => (a >> b) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => truth(left < right),
+            "<=" => truth(left <= right),
+            ">" => truth(left > right),
+            ">=" => truth(left >= right),
+            "==" => truth(left == right),
+            _ => truth(left != right),
+        })
+    }
+
+    fn calc_term(&mut self) -> Result<f64, String> {
+        let word = self.word()?;
+
+        Ok(match word.as_str() {
+            "(" => {
+                let value = self.calc_expression()?;
+                self.expect(")")?;
+                value
+            }
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => if self.calc_term()? == 0.0 { 1.0 } else { 0.0 },
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "tan" => self.calc_term()?.tan(),
+            "exp" => self.calc_term()?.exp(),
+            "log" => self.calc_term()?.ln(),
+            "sign" => self.calc_term()?.signum(),
+            "ceil" => self.calc_term()?.ceil(),
+            "floor" => self.calc_term()?.floor(),
+            "@" => {
+                let address = self.calc_term()? as usize;
+                address
+                    .checked_sub(START)
+                    .and_then(|i| self.rom.get(i))
+                    .map(|&b| f64::from(b))
+                    .unwrap_or(0.0)
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => match parse_number(&word)
+                .or_else(|| self.constants.get(&word).copied())
+                .or_else(|| self.labels.get(&word).map(|&a| a as f64))
+            {
+                Some(n) => n,
+                None => return Err(format!("undefined name: {}", word)),
+            },
+        })
+    }
+}
+
+fn is_binary_operator(word: &str) -> bool {
+    [
+        "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", "<=", ">", ">=", "==", "!=",
+    ]
+    .contains(&word)
+}
+
+fn is_keyword(word: &str) -> bool {
+    [
+        ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", "<", ">", "<=", ">=", "key", "-key",
+        "then", "begin", "else", "end", "loop", "again", "while", "if", "{", "}",
+    ]
+    .contains(&word)
+}
+
+/// Parses decimal, `0x` hexadecimal and `0b` binary numbers, which may
+/// be negative.
+fn parse_number(word: &str) -> Option<f64> {
+    let (negative, digits) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word),
+    };
+
+    let n = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -n as f64 } else { n as f64 })
+}
+
+fn integer(n: f64, min: i64, max: i64, what: &str) -> Result<i64, String> {
+    let n = n.floor() as i64;
+    if n < min || n > max {
+        return Err(format!("{} does not fit in a {}", n, what));
+    }
+    Ok(n)
+}
+
+/// Bytes can be written as signed or unsigned.
+fn byte(n: f64) -> Result<u8, String> {
+    integer(n, -128, 255, "byte").map(|n| n as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            : main
+              i := smile
+              v0 := 0
+              loop
+                sprite v0 v1 4
+                v0 += 1
+                if v0 == 60 then v0 := 0 # wrap around
+              again
+
+            : smile
+              0x24 0x00 0x81 0x7E
+        ";
+
+        // main comes first, so there is no jump to it.
+        let program = vec![
+            0xA2, 0x0E, 0x60, 0x00, 0xD0, 0x14, 0x70, 0x01, 0x40, 0x3C, 0x60, 0x00, 0x12, 0x04, 0x24, 0x00, 0x81, 0x7E,
+        ];
+        assert_eq!(Ok(program), assemble(source));
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "
+            : double
+              v0 += v0
+              ;
+
+            : main
+              loop
+                while v1 != 3
+                v1 += 1
+                double
+              again
+              if v0 > v2 begin
+                v3 := 1
+              else
+                v3 := -1
+              end
+              if v4 key then jump main
+        ";
+
+        let program = vec![
+            0x12, 0x06, 0x80, 0x04, 0x00, 0xEE, 0x41, 0x03, 0x12, 0x10, 0x71, 0x01, 0x22, 0x02, 0x12, 0x06, 0x8F, 0x20,
+            0x8F, 0x05, 0x3F, 0x00, 0x12, 0x1C, 0x63, 0x01, 0x12, 0x1E, 0x63, 0xFF, 0xE4, 0xA1, 0x12, 0x06,
+        ];
+        assert_eq!(Ok(program), assemble(source));
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = "
+            :const SPEED 3
+            :alias x v5
+            :macro step register amount { register += amount }
+            :calc DOUBLE { SPEED * 2 }
+
+            : main
+              x := SPEED
+              step x DOUBLE
+              :unpack 0xA data
+              i := long data
+              :byte { DOUBLE + 1 }
+            : data
+        ";
+
+        let program = vec![0x65, 0x03, 0x75, 0x06, 0x60, 0xA2, 0x61, 0x0D, 0xF0, 0x00, 0x02, 0x0D, 0x07];
+        assert_eq!(Ok(program), assemble(source));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source).unwrap_err();
+
+        assert_eq!("line 2: undefined name: nowhere", error(": main\n  jump nowhere"));
+        assert_eq!("the program has no main label", error(": start\n  clear"));
+        assert_eq!("line 2: this block is never closed", error(": main\n  loop"));
+        assert_eq!("line 1: 256 does not fit in a byte", error(": main v0 := 256"));
+        assert_eq!("line 1: too big", error(":assert \"too big\" { 1 > 2 }"));
+        assert!(error(":macro forever { forever }\n: main forever").contains("too many macro expansions"));
+        assert!(error(": main\n  :stringmode").contains("not supported"));
+    }
+}
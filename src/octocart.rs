@@ -0,0 +1,231 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Octo cartridges ("octocarts"): GIF images that carry a program for the
+//! Octo CHIP-8 environment together with the options it needs.
+//!
+//! The data is hidden in the two lowest bits of the colour index of every
+//! pixel, four pixels per byte starting with the highest bits, through
+//! all the frames of the image. It is a 32-bit big-endian length followed
+//! by that many bytes of JSON: `{"options": {...}, "program": "..."}`.
+
+use serde::Deserialize;
+
+use crate::chip8::mem::ROM_SIZE;
+use crate::chip8::platform::Platform;
+use crate::config::{Config, EmulationConfig, QuirksConfig, VideoConfig};
+use crate::octo;
+use crate::rom;
+
+/// Options that Octo keeps with a program. Only the ones that mean
+/// something to this emulator are read.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OctoOptions {
+    /// Instructions per frame.
+    pub tickrate: Option<usize>,
+    pub background_color: Option<String>,
+    pub fill_color: Option<String>,
+    pub fill_color2: Option<String>,
+    pub blend_color: Option<String>,
+    pub shift_quirks: Option<bool>,
+    pub load_store_quirks: Option<bool>,
+    pub jump_quirks: Option<bool>,
+    pub clip_quirks: Option<bool>,
+    pub logic_quirks: Option<bool>,
+    /// Largest program size allowed, which tells which platform the
+    /// program was written for.
+    pub max_size: Option<usize>,
+}
+
+/// The contents of an Octo cartridge.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Cartridge {
+    pub options: OctoOptions,
+    /// Octo source code of the program.
+    pub program: String,
+}
+
+/// Tells whether `data` looks like a GIF image.
+pub fn is_octocart(data: &[u8]) -> bool {
+    data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a")
+}
+
+impl Cartridge {
+    /// Extracts the program and its options from a cartridge image.
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let error = |e: &dyn std::fmt::Display| format!("invalid Octo cartridge: {}", e);
+
+        let mut decoder = gif::DecodeOptions::new().read_info(data).map_err(|e| error(&e))?;
+        let mut bytes = Vec::new();
+        let mut byte = 0_u8;
+        let mut bits = 0;
+
+        while let Some(frame) = decoder.read_next_frame().map_err(|e| error(&e))? {
+            for index in frame.buffer.iter() {
+                byte = byte << 2 | (index & 0x3);
+                bits += 2;
+                if bits == 8 {
+                    bytes.push(byte);
+                    bits = 0;
+                }
+            }
+        }
+
+        if bytes.len() < 4 {
+            return Err(error(&"no data"));
+        }
+
+        let length = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let json = bytes.get(4..4 + length).ok_or_else(|| error(&"truncated data"))?;
+
+        serde_json::from_slice(json).map_err(|e| error(&e))
+    }
+
+    /// Settings taken from the options of the cartridge, which go on top
+    /// of the configuration file but below the section of the ROM.
+    pub fn config(&self) -> Config {
+        let options = &self.options;
+
+        // Octo colours are in the order background, first plane, second
+        // plane and both planes, like ours.
+        let colors = [
+            &options.background_color,
+            &options.fill_color,
+            &options.fill_color2,
+            &options.blend_color,
+        ];
+        let colors = colors.iter().map(|&c| c.clone()).collect::<Option<Vec<String>>>();
+
+        Config {
+            video: VideoConfig { colors, ..VideoConfig::default() },
+            emulation: EmulationConfig {
+                instructions_per_frame: options.tickrate,
+                xo_chip: options.max_size.map(|size| size > ROM_SIZE),
+                ..EmulationConfig::default()
+            },
+            quirks: QuirksConfig {
+                shift: options.shift_quirks,
+                load_store: options.load_store_quirks,
+                jump: options.jump_quirks,
+                clip: options.clip_quirks,
+                logic: options.logic_quirks,
+                ..QuirksConfig::default()
+            },
+            ..Config::default()
+        }
+    }
+
+    /// Returns the program as a binary, assembling its source code unless
+    /// it is nothing but bytes.
+    pub fn binary(&self, platform: &Platform) -> Result<Vec<u8>, String> {
+        let code = self
+            .program
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if rom::is_hex_dump(&code) {
+            return rom::parse(code.as_bytes(), platform);
+        }
+
+        // Octo assembles every program for 0x200.
+        if platform.load_address != 0x200 {
+            return Err(format!(
+                "Octo programs are loaded at 0x200, not at {:#X}",
+                platform.load_address
+            ));
+        }
+
+        let program = octo::assemble(&self.program)?;
+        rom::check_size(program, platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::Cartridge;
+    use crate::chip8::platform::Platform;
+    use crate::rom;
+
+    // Builds a cartridge the way Octo does, over a picture that only uses
+    // the colour 0x40.
+    fn cartridge(json: &str) -> Vec<u8> {
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|byte| (0..4).rev().map(move |i| 0x40 | (byte >> (2 * i)) & 0x3))
+            .collect();
+        let (width, height) = (32, (pixels.len() as u16).div_ceil(32));
+        pixels.resize(width as usize * height as usize, 0x40);
+
+        let mut image = Vec::new();
+        let palette = vec![0; 256 * 3];
+        let mut encoder = gif::Encoder::new(&mut image, width, height, &palette).unwrap();
+        let frame = gif::Frame { width, height, buffer: Cow::Owned(pixels), ..gif::Frame::default() };
+        encoder.write_frame(&frame).unwrap();
+        drop(encoder);
+
+        image
+    }
+
+    #[test]
+    fn test_octocart() {
+        let json = r##"{
+            "options": {
+                "tickrate": 20, "maxSize": 65024, "shiftQuirks": false, "clipQuirks": true,
+                "backgroundColor": "#000000", "fillColor": "#FF0000",
+                "fillColor2": "#00FF00", "blendColor": "#FFFFFF", "fontStyle": "octo"
+            },
+            "program": "0x00 0xE0 # clear the screen\n0x12 0x02"
+        }"##;
+        let cart = Cartridge::parse(&cartridge(json)).unwrap();
+
+        let config = cart.config();
+        assert_eq!(Some(20), config.emulation.instructions_per_frame);
+        assert_eq!(Some(true), config.emulation.xo_chip);
+        assert_eq!((Some(false), Some(true), None), (config.quirks.shift, config.quirks.clip, config.quirks.jump));
+        assert_eq!("#FF0000", config.video.colors.unwrap()[1]);
+
+        let program = vec![0x00, 0xE0, 0x12, 0x02];
+        assert_eq!(Ok(program.clone()), cart.binary(&Platform::default()));
+        assert_eq!(Ok(program), rom::parse(&cartridge(json), &Platform::default()));
+    }
+
+    #[test]
+    fn test_octocart_source() {
+        let json = r#"{"options": {}, "program": ": main\n  clear\n  loop again"}"#;
+        let cart = Cartridge::parse(&cartridge(json)).unwrap();
+
+        assert_eq!(None, cart.config().video.colors);
+        assert_eq!(Ok(vec![0x00, 0xE0, 0x12, 0x02]), cart.binary(&Platform::default()));
+        let eti_660 = Platform { load_address: 0x600, ..Platform::default() };
+        assert!(cart.binary(&eti_660).unwrap_err().contains("loaded at 0x200"));
+        assert!(Cartridge::parse(b"GIF89a").is_err());
+    }
+}
@@ -0,0 +1,136 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A colour made of its red, green and blue components.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses a colour written as `RRGGBB` or `#RRGGBB`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("invalid colour: {}", s));
+        }
+
+        let v = u32::from_str_radix(hex, 16).unwrap();
+        Ok(Rgb((v >> 16) as u8, (v >> 8) as u8, v as u8))
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+/// The colours used to render the display. A pixel with the value `v`
+/// is drawn with `colors[v]`: 0 is the background and 1 the foreground.
+/// XO-CHIP draws on two bit planes, so 2 is used for pixels that are only
+/// set in the second plane and 3 for pixels set in both.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+/// Built-in palettes that can be selected by name.
+pub const THEMES: &[(&str, [u32; 4])] = &[
+    ("chippy",        [0x000000, 0xFFFF00, 0x0080FF, 0xFFFFFF]),
+    ("classic",       [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("lcd-green",     [0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230]),
+    ("amber",         [0x140C00, 0xFFB000, 0x8C5A00, 0xFFD780]),
+    // Palettes that ship with Octo.
+    ("octo",          [0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+    ("octo-lcd",      [0xF2FFF2, 0x5B8C7C, 0xADD9BC, 0x0D1A1A]),
+    ("octo-hotdog",   [0x000000, 0xFF0000, 0xFFFF00, 0xFFFFFF]),
+    ("octo-gray",     [0xAAAAAA, 0x000000, 0xFFFFFF, 0x666666]),
+    ("octo-cga0",     [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00]),
+    ("octo-cga1",     [0x000000, 0xFF00FF, 0x00FFFF, 0xFFFFFF]),
+    // Black and white for the two main colours. The XO-CHIP colours come
+    // from the Okabe-Ito palette, which stays distinguishable with the
+    // common colour vision deficiencies.
+    ("high-contrast", [0x000000, 0xFFFFFF, 0x56B4E9, 0xE69F00]),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::theme("chippy").unwrap()
+    }
+}
+
+impl Palette {
+    /// Returns the built-in palette called `name`, if there is one.
+    pub fn theme(name: &str) -> Option<Self> {
+        THEMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, colors)| {
+                let rgb = |c: u32| Rgb((c >> 16) as u8, (c >> 8) as u8, c as u8);
+                Palette { colors: [rgb(colors[0]), rgb(colors[1]), rgb(colors[2]), rgb(colors[3])] }
+            })
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.colors[1]
+    }
+
+    /// Returns the colour of a pixel with the value `v`.
+    pub fn color(&self, v: u8) -> Rgb {
+        self.colors[v as usize & 0x3]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, Rgb, THEMES};
+
+    #[test]
+    fn test_parse_rgb() {
+        assert_eq!(Ok(Rgb(0x12, 0xAB, 0xFF)), "12abff".parse());
+        assert_eq!(Ok(Rgb(0x99, 0x66, 0x00)), "#996600".parse());
+        assert!("#99660".parse::<Rgb>().is_err());
+        assert!("#99660G".parse::<Rgb>().is_err());
+        assert!("+12345".parse::<Rgb>().is_err());
+        assert_eq!("#0080FF", Rgb(0, 128, 255).to_string());
+    }
+
+    #[test]
+    fn test_themes() {
+        assert!(THEMES.iter().all(|(name, _)| Palette::theme(name).is_some()));
+        assert_eq!(None, Palette::theme("nope"));
+
+        let octo = Palette::theme("octo").unwrap();
+        assert_eq!(Rgb(0x99, 0x66, 0x00), octo.background());
+        assert_eq!(Rgb(0xFF, 0xCC, 0x00), octo.foreground());
+        assert_eq!(Rgb(0x66, 0x22, 0x00), octo.color(3));
+    }
+}
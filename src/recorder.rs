@@ -0,0 +1,264 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use gif::{Encoder, Frame, Repeat};
+
+use crate::chip8::display::Display;
+use crate::palette::{Palette,Rgb};
+
+// Number of frames captured per second.
+const FRAME_RATE: u64 = 60;
+
+// Most GIF viewers show frames with a shorter delay (in hundredths of
+// a second) for much longer than requested, so no frame gets less.
+const MIN_GIF_DELAY: u64 = 2;
+
+/// Records the display once per frame into an animated GIF, or into a
+/// raw Y4M stream that can be piped into an external encoder.
+pub(crate) struct Recorder {
+    output: Output,
+    display_size: (usize, usize),
+    scale: usize,
+}
+
+enum Output {
+    Gif(GifOutput),
+    Y4m(Y4mOutput),
+}
+
+impl Recorder {
+    /// Creates a recorder that writes to `path`. The format is picked from
+    /// the file extension: `.gif` for an animated GIF and `.y4m` for a Y4M
+    /// stream. A `-` writes the Y4M stream to the standard output.
+    /// `display_size` is the resolution of the display being recorded.
+    pub fn new(path: &Path, palette: &Palette, display_size: (usize, usize), scale: usize) -> Result<Self, String> {
+        let width = display_size.0 * scale;
+        let height = display_size.1 * scale;
+
+        let output = if path == Path::new("-") {
+            Output::Y4m(Y4mOutput::new(Box::new(io::stdout()), palette, width, height)?)
+        } else {
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            let writer = BufWriter::new(file);
+
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("gif") => Output::Gif(GifOutput::new(writer, palette, width, height)?),
+                Some("y4m") => Output::Y4m(Y4mOutput::new(Box::new(writer), palette, width, height)?),
+                _ => return Err(format!("unsupported recording format: {}", path.display())),
+            }
+        };
+
+        Ok(Recorder { output, display_size, scale })
+    }
+
+    /// Captures one frame. It should be called exactly once per 1/60 s
+    /// for the recording to play back at the right speed.
+    pub fn capture(&mut self, display: &Display) -> Result<(), String> {
+        // Neither format can change size halfway through, e.g. when a ROM
+        // for another platform is picked in the launcher.
+        if (display.width(), display.height()) != self.display_size {
+            return Err("the display changed size".to_string());
+        }
+
+        let frame = display.to_indexed(self.scale);
+
+        match &mut self.output {
+            Output::Gif(gif) => gif.capture(frame),
+            Output::Y4m(y4m) => y4m.capture(&frame),
+        }
+    }
+
+    /// Writes whatever is still buffered and closes the recording.
+    pub fn finish(self) -> Result<(), String> {
+        match self.output {
+            Output::Gif(mut gif) => gif.flush(gif.frames),
+            Output::Y4m(mut y4m) => y4m.writer.flush().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+struct GifOutput {
+    encoder: Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+    /// Frame currently on screen. It is only written once a different
+    /// frame shows up, because that is when its duration is known.
+    pending: Option<Vec<u8>>,
+    /// Number of frames captured so far.
+    frames: u64,
+    /// Total length of the frames written so far, in hundredths of a second.
+    written: u64,
+}
+
+impl GifOutput {
+    fn new(writer: BufWriter<File>, palette: &Palette, width: usize, height: usize) -> Result<Self, String> {
+        let colors: Vec<u8> = palette.colors
+            .iter()
+            .flat_map(|c| vec![c.0, c.1, c.2])
+            .collect();
+
+        let too_large = |_| format!("{}x{} is too large for a GIF", width, height);
+        let (width, height) = (
+            u16::try_from(width).map_err(too_large)?,
+            u16::try_from(height).map_err(too_large)?,
+        );
+
+        let mut encoder = Encoder::new(writer, width, height, &colors)
+            .map_err(|e| e.to_string())?;
+        encoder.set_repeat(Repeat::Infinite).map_err(|e| e.to_string())?;
+
+        Ok(GifOutput {
+            encoder,
+            width,
+            height,
+            pending: None,
+            frames: 0,
+            written: 0,
+        })
+    }
+
+    fn capture(&mut self, frame: Vec<u8>) -> Result<(), String> {
+        self.frames += 1;
+
+        if self.pending.as_ref() != Some(&frame) {
+            self.flush(self.frames - 1)?;
+            self.pending = Some(frame);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the pending frame, which stays on screen until `end` frames
+    /// into the recording. Identical frames are merged into one, so its
+    /// delay covers all of them.
+    fn flush(&mut self, end: u64) -> Result<(), String> {
+        if let Some(pixels) = self.pending.take() {
+            let end = (end * 100 + FRAME_RATE / 2) / FRAME_RATE;
+
+            // Frames too short to be shown properly are stretched to
+            // MIN_GIF_DELAY, and the time they take over is taken back
+            // from the frames after them.
+            let delay = end.saturating_sub(self.written).max(MIN_GIF_DELAY);
+            let mut frame = Frame::from_indexed_pixels(self.width, self.height, &pixels, None);
+            frame.delay = u16::try_from(delay).unwrap_or(u16::MAX);
+
+            self.encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+            self.written += u64::from(frame.delay);
+        }
+
+        Ok(())
+    }
+}
+
+struct Y4mOutput {
+    writer: Box<dyn Write>,
+    /// Y, Cb and Cr components of each palette colour.
+    colors: [[u8; 3]; 4],
+}
+
+impl Y4mOutput {
+    fn new(mut writer: Box<dyn Write>, palette: &Palette, width: usize, height: usize) -> Result<Self, String> {
+        writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FRAME_RATE)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Y4mOutput {
+            writer,
+            colors: [
+                to_ycbcr(palette.colors[0]),
+                to_ycbcr(palette.colors[1]),
+                to_ycbcr(palette.colors[2]),
+                to_ycbcr(palette.colors[3]),
+            ],
+        })
+    }
+
+    fn capture(&mut self, frame: &[u8]) -> Result<(), String> {
+        let mut data = Vec::with_capacity(6 + frame.len() * 3);
+        data.extend_from_slice(b"FRAME\n");
+
+        for plane in 0..3 {
+            data.extend(frame.iter().map(|&v| self.colors[v as usize][plane]));
+        }
+
+        self.writer.write_all(&data).map_err(|e| e.to_string())
+    }
+}
+
+// Converts a colour to the limited range BT.601 YCbCr used by Y4M.
+fn to_ycbcr(c: Rgb) -> [u8; 3] {
+    let (r, g, b) = (c.0 as f32, c.1 as f32, c.2 as f32);
+
+    [
+        (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8,
+        (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8,
+        (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::Recorder;
+    use crate::chip8::display::Display;
+    use crate::palette::Palette;
+
+    #[test]
+    fn test_gif_merges_identical_frames() {
+        let path = std::env::temp_dir().join("chippy_test_gif_merges_identical_frames.gif");
+        let mut recorder = Recorder::new(&path, &Palette::default(), (64, 32), 1).unwrap();
+
+        let blank = Display::default();
+        let mut lit = Display::default();
+        lit[(3, 4)] = 1;
+
+        (0..30).for_each(|_| recorder.capture(&blank).unwrap());
+        (0..90).for_each(|_| recorder.capture(&lit).unwrap());
+        recorder.capture(&blank).unwrap();
+        recorder.capture(&lit).unwrap(); // stretched from 1/100 s to 2/100 s
+        (0..3).for_each(|_| recorder.capture(&blank).unwrap()); // 5/100 s, minus the 1/100 s before
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(File::open(&path).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+
+        assert_eq!(vec![50, 150, 2, 2, 4], delays);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_gif_too_large() {
+        let path = std::env::temp_dir().join("chippy_test_gif_too_large.gif");
+        let error = Recorder::new(&path, &Palette::default(), (128, 64), 1024).err().unwrap();
+        assert_eq!("131072x65536 is too large for a GIF", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,297 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Reads CHIP-8 programs, either as raw binaries, as text (Intel HEX files
+//! or plain hexadecimal dumps) or from Octo cartridges.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::chip8::mem::XO_CHIP_MEMORY_SIZE;
+use crate::chip8::platform::Platform;
+use crate::config::Config;
+use crate::emulator::Options;
+use crate::metadata::Metadata;
+use crate::octocart::{self, Cartridge};
+
+/// Reads the program at `path`, or from the standard input if it is `-`,
+/// and works out the options it runs with from the settings behind
+/// `options`.
+pub fn open(path: &Path, options: &Options) -> Result<(Vec<u8>, Options), String> {
+    let data = read(path)?;
+    let mut options = options.for_rom(Some(path), Some(&data))?;
+    let program = parse(&data, &options.platform).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if options.detect_hires {
+        options.platform = options.platform.for_program(&program);
+    }
+
+    Ok((program, options))
+}
+
+/// Settings that come with a ROM: the options of an Octo cartridge, and
+/// then those of the sidecar next to it.
+pub fn settings(path: &Path, data: &[u8]) -> Result<Vec<Config>, String> {
+    let mut settings = Vec::new();
+
+    if octocart::is_octocart(data) {
+        let cartridge = Cartridge::parse(data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings.push(cartridge.config());
+    }
+
+    if path != Path::new("-") {
+        if let Some(metadata) = Metadata::find(path, data)? {
+            settings.push(metadata.config());
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Reads a program from `path`, or from the standard input if it is `-`.
+/// `platform` is the machine it is loaded into, whose memory sets how
+/// large the program can be.
+pub fn load(path: &Path, platform: &Platform) -> Result<Vec<u8>, String> {
+    read(path).and_then(|data| parse(&data, platform).map_err(|e| format!("{}: {}", path.display(), e)))
+}
+
+/// Reads a file as it is, or the standard input if `path` is `-`.
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    let data = if path == Path::new("-") {
+        let mut data = Vec::new();
+        io::stdin().read_to_end(&mut data).map(|_| data)
+    } else {
+        fs::read(path)
+    };
+
+    data.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Works out the format of a program and returns its binary form.
+pub fn parse(data: &[u8], platform: &Platform) -> Result<Vec<u8>, String> {
+    if octocart::is_octocart(data) {
+        return Cartridge::parse(data)?.binary(platform);
+    }
+
+    let program = match std::str::from_utf8(data) {
+        Ok(text) if is_intel_hex(text) => parse_intel_hex(text, platform.load_address)?,
+        Ok(text) if is_hex_dump(text) => parse_hex_dump(text)?,
+        _ => data.to_vec(),
+    };
+
+    check_size(program, platform)
+}
+
+/// Makes sure that `program` fits in the memory of `platform`.
+pub(crate) fn check_size(program: Vec<u8>, platform: &Platform) -> Result<Vec<u8>, String> {
+    let max_size = platform.max_program_size();
+
+    if program.is_empty() {
+        Err("the ROM is empty".to_string())
+    } else if program.len() > max_size && platform.memory_size < XO_CHIP_MEMORY_SIZE {
+        Err(format!(
+            "the ROM is {} bytes long, but only {} fit in memory (XO-CHIP programs can be up to {} bytes long with --xo-chip)",
+            program.len(),
+            max_size,
+            XO_CHIP_MEMORY_SIZE - platform.load_address
+        ))
+    } else if program.len() > max_size {
+        Err(format!("the ROM is {} bytes long, but only {} fit in memory", program.len(), max_size))
+    } else {
+        Ok(program)
+    }
+}
+
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().map(str::trim).filter(|l| !l.is_empty())
+}
+
+fn is_intel_hex(text: &str) -> bool {
+    lines(text).next().is_some() && lines(text).all(|l| l.starts_with(':'))
+}
+
+// Hexadecimal digits separated by white space or commas, optionally with
+// a 0x prefix, e.g. `00E0 A22A` or `0x00, 0xE0`.
+pub(crate) fn is_hex_dump(text: &str) -> bool {
+    tokens(text).next().is_some()
+        && tokens(text).all(|t| !t.is_empty() && t.len() % 2 == 0 && t.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn tokens(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .map(|t| t.strip_prefix("0x").or_else(|| t.strip_prefix("0X")).unwrap_or(t))
+}
+
+fn parse_hex_dump(text: &str) -> Result<Vec<u8>, String> {
+    tokens(text).flat_map(|t| (0..t.len()).step_by(2).map(move |i| &t[i..i + 2])).map(hex_byte).collect()
+}
+
+fn hex_byte(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s, 16).map_err(|_| format!("invalid hexadecimal byte: {}", s))
+}
+
+/// Reads an Intel HEX file. Programs are usually placed at the load
+/// address, 0x200 on most platforms, but they may also start at 0; either
+/// way the result starts with the first byte of the program.
+fn parse_intel_hex(text: &str, load_address: usize) -> Result<Vec<u8>, String> {
+    let mut chunks: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut base = 0;
+
+    for (n, line) in lines(text).enumerate() {
+        let error = |e: &str| format!("line {}: {}", n + 1, e);
+
+        let hex = &line[1..];
+        if hex.len() < 10 || hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(error("malformed record"));
+        }
+
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| hex_byte(&hex[i..i + 2]))
+            .collect::<Result<Vec<u8>, String>>()
+            .map_err(|e| error(&e))?;
+
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(error("wrong record length"));
+        }
+        if bytes.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(error("wrong checksum"));
+        }
+
+        let address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let data = &bytes[4..4 + length];
+
+        match bytes[3] {
+            0x00 => chunks.push((base + address, data.to_vec())),
+            0x01 => break,
+            0x02 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 4,
+            0x04 if length == 2 => base = ((data[0] as usize) << 8 | data[1] as usize) << 16,
+            // Start addresses make no sense for CHIP-8 programs.
+            0x03 | 0x05 => {}
+            _ => return Err(error("unsupported record")),
+        }
+    }
+
+    let start = match chunks.iter().map(|(address, _)| *address).min() {
+        Some(address) if address >= load_address => load_address,
+        _ => 0,
+    };
+    let end = chunks.iter().map(|(address, data)| address + data.len()).max().unwrap_or(start);
+    if end - start > XO_CHIP_MEMORY_SIZE {
+        return Err("the program is spread over more than 64 KiB".to_string());
+    }
+
+    let mut program = vec![0; end - start];
+    for (address, data) in chunks {
+        program[address - start..address - start + data.len()].copy_from_slice(&data);
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{open, parse};
+    use crate::chip8::mem::XO_CHIP_MEMORY_SIZE;
+    use crate::chip8::platform::Platform;
+    use crate::emulator::Options;
+
+    #[test]
+    fn test_rom_size() {
+        let chip_8 = Platform::default();
+        assert!(parse(&[], &chip_8).is_err());
+        assert_eq!(Ok(vec![0x12; 3584]), parse(&[0x12; 3584], &chip_8));
+
+        let error = parse(&[0x12; 3585], &chip_8).unwrap_err();
+        assert!(error.contains("3585 bytes long, but only 3584 fit"));
+        assert!(error.contains("--xo-chip"));
+
+        let xo_chip = Platform { memory_size: XO_CHIP_MEMORY_SIZE, ..chip_8 };
+        assert!(parse(&[0x12; 3585], &xo_chip).is_ok());
+        assert!(parse(&vec![0x12; 65025], &xo_chip).is_err());
+
+        let eti_660 = Platform { load_address: 0x600, ..chip_8 };
+        assert!(parse(&[0x12; 2560], &eti_660).is_ok());
+        assert!(parse(&[0x12; 2561], &eti_660).unwrap_err().contains("only 2560 fit"));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert_eq!(Ok(vec![0x00, 0xE0, 0xA2, 0x2A]), parse(b"00E0 a22a\n", &Platform::default()));
+        assert_eq!(Ok(vec![0x00, 0xE0, 0x12]), parse(b"0x00, 0xE0,\r\n0x12", &Platform::default()));
+        // Anything else is a binary.
+        assert_eq!(Ok(b"00E0 A2G2".to_vec()), parse(b"00E0 A2G2", &Platform::default()));
+    }
+
+    #[test]
+    fn test_intel_hex() {
+        let text = ":0402000000E0A22A4E\n:02020400120ADC\n:00000001FF\n";
+        assert_eq!(Ok(vec![0x00, 0xE0, 0xA2, 0x2A, 0x12, 0x0A]), parse(text.as_bytes(), &Platform::default()));
+
+        // Programs starting at 0 are fine as well.
+        assert_eq!(Ok(vec![0x12, 0x00]), parse(b":020000001200EC\n", &Platform::default()));
+
+        assert!(parse(b":020000001200ED\n", &Platform::default()).unwrap_err().contains("checksum"));
+        assert!(parse(b":0300000012ED\n", &Platform::default()).unwrap_err().contains("length"));
+    }
+
+    #[test]
+    fn test_open() {
+        let dir = std::env::temp_dir().join(format!("chippy-rom-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ZERO"), [0x12, 0x00]).unwrap();
+        fs::write(dir.join("ZERO.chip8.toml"), "platform = \"eti-660\"\n[emulation]\ninstructions-per-frame = 15").unwrap();
+
+        let options = Options {
+            config: toml::from_str("[roms.ZERO]\nquirks.clip = false").unwrap(),
+            flags: toml::from_str("video.theme = \"amber\"").unwrap(),
+            frames: 42,
+            ..Options::default()
+        };
+
+        // The sidecar, the ROM's section of the configuration and the flags
+        // all count, and the options that don't come from them are kept.
+        let (rom, rom_options) = open(&dir.join("ZERO"), &options).unwrap();
+        assert_eq!(vec![0x12, 0x00], rom);
+        assert_eq!(0x600, rom_options.platform.load_address);
+        assert_eq!(15, rom_options.instructions_per_frame);
+        assert!(!rom_options.quirks.clip);
+        assert!(rom_options.quirks.key_release);
+        assert_eq!(crate::palette::Palette::theme("amber").unwrap(), rom_options.palette);
+        assert_eq!(42, rom_options.frames);
+
+        // Hi-res programs are only recognised when no platform was picked.
+        fs::write(dir.join("HIRES"), [0x12, 0x60]).unwrap();
+        assert!(open(&dir.join("HIRES"), &Options::default()).unwrap().1.platform.is_hires());
+        let cosmac_vip = Options { flags: toml::from_str("emulation.platform = \"cosmac-vip\"").unwrap(), ..Options::default() };
+        assert_eq!((64, 32), open(&dir.join("HIRES"), &cosmac_vip).unwrap().1.platform.display_size);
+
+        fs::remove_dir_all(&dir).unwrap();
+        let error = open(&dir.join("ZERO"), &options).map(|_| ()).unwrap_err();
+        assert!(error.starts_with(&format!("{}: ", dir.join("ZERO").display())));
+    }
+}
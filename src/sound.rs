@@ -0,0 +1,319 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+// How long the tone takes to fade in and out, in seconds. Starting or
+// stopping the wave abruptly makes the speaker pop.
+const ATTACK: f32 = 0.005;
+const RELEASE: f32 = 0.01;
+
+// Number of frames a Timeline remembers.
+const TIMELINE_FRAMES: usize = 64;
+// Frames buffered before the audio starts playing them, which absorbs
+// the jitter of the emulator loop.
+const LATENCY: u64 = 3;
+// When the audio falls this many frames behind, it skips ahead.
+const MAX_LATENCY: u64 = 8;
+
+/// Shape of the wave played by the buzzer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    /// White noise, changing value twice per period of the tone.
+    Noise,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sine" => Ok(Waveform::Sine),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Waveform::Square => "square",
+            Waveform::Triangle => "triangle",
+            Waveform::Sine => "sine",
+            Waveform::Noise => "noise",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The sound of the buzzer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tone {
+    /// Frequency in Hz.
+    pub frequency: f32,
+    pub waveform: Waveform,
+    /// From 0 (silent) to 1 (full scale).
+    pub volume: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: 240.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+        }
+    }
+}
+
+/// Generates the samples of the buzzer, fading it in and out whenever it
+/// is turned on or off.
+pub(crate) struct Synth {
+    tone: Tone,
+    phase: f32,
+    phase_inc: f32,
+    /// Current volume of the envelope, from 0 to 1.
+    level: f32,
+    attack_step: f32,
+    release_step: f32,
+    noise: f32,
+    seed: u32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone, sample_rate: u32) -> Self {
+        let sample_rate = sample_rate as f32;
+
+        Synth {
+            tone,
+            phase: 0.0,
+            phase_inc: tone.frequency / sample_rate,
+            level: 0.0,
+            attack_step: 1.0 / (ATTACK * sample_rate),
+            release_step: 1.0 / (RELEASE * sample_rate),
+            noise: 0.0,
+            seed: 0x1234_5678,
+        }
+    }
+
+    /// Returns the next sample, with the buzzer on if `on` is true.
+    pub fn next(&mut self, on: bool) -> f32 {
+        self.level = if on {
+            (self.level + self.attack_step).min(1.0)
+        } else {
+            (self.level - self.release_step).max(0.0)
+        };
+
+        if self.level == 0.0 {
+            // Start the wave from the beginning next time.
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let sample = match self.tone.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (2.0 * PI * self.phase).sin(),
+            Waveform::Noise => self.noise,
+        };
+
+        let phase = self.phase + self.phase_inc;
+        if self.tone.waveform == Waveform::Noise && (phase >= 1.0 || (self.phase < 0.5 && phase >= 0.5)) {
+            self.noise = self.random();
+        }
+        self.phase = phase % 1.0;
+
+        sample * self.level * self.tone.volume
+    }
+
+    // Xorshift, mapped to -1..1.
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Whether the buzzer is on in each emulated frame. The emulator appends
+/// frames as it runs them and the audio thread plays them back, without
+/// any locks: there must be a single thread writing and a single one
+/// reading.
+pub(crate) struct Timeline {
+    frames: Vec<AtomicBool>,
+    /// Number of frames appended so far.
+    written: AtomicU64,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline {
+            frames: (0..TIMELINE_FRAMES).map(|_| AtomicBool::new(false)).collect(),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    /// Appends the state of the buzzer during the next frame.
+    pub fn push(&self, on: bool) {
+        let n = self.written.load(Ordering::Relaxed);
+        self.frames[n as usize % TIMELINE_FRAMES].store(on, Ordering::Relaxed);
+        self.written.store(n + 1, Ordering::Release);
+    }
+}
+
+/// Plays the frames of a `Timeline` back at 60 frames per second of
+/// audio samples, so the buzzer sounds for exactly as long as the sound
+/// timer says, whatever the host was doing meanwhile.
+pub(crate) struct Playback {
+    samples_per_frame: f64,
+    /// Frame being played.
+    frame: u64,
+    /// Samples of the frame played so far.
+    position: f64,
+    /// Waiting for the emulator to get LATENCY frames ahead.
+    buffering: bool,
+}
+
+impl Playback {
+    pub fn new(sample_rate: u32) -> Self {
+        Playback {
+            samples_per_frame: sample_rate as f64 / 60.0,
+            frame: 0,
+            position: 0.0,
+            buffering: true,
+        }
+    }
+
+    /// Tells whether the buzzer is on for the next sample.
+    pub fn next(&mut self, timeline: &Timeline) -> bool {
+        let written = timeline.written.load(Ordering::Acquire);
+
+        if written > self.frame + MAX_LATENCY {
+            self.frame = written - LATENCY;
+            self.position = 0.0;
+        }
+
+        if self.buffering && written < self.frame + LATENCY {
+            return false;
+        }
+        self.buffering = false;
+
+        if self.frame >= written {
+            // The emulator is late, or paused.
+            self.buffering = true;
+            return false;
+        }
+
+        let on = timeline.frames[self.frame as usize % TIMELINE_FRAMES].load(Ordering::Relaxed);
+
+        self.position += 1.0;
+        if self.position >= self.samples_per_frame {
+            self.position -= self.samples_per_frame;
+            self.frame += 1;
+        }
+
+        on
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Playback, Synth, Timeline, Tone, Waveform, LATENCY, RELEASE};
+
+    #[test]
+    fn test_parse_waveform() {
+        assert_eq!(Ok(Waveform::Triangle), "triangle".parse());
+        assert_eq!("noise", Waveform::Noise.to_string());
+        assert!("sawtooth".parse::<Waveform>().is_err());
+    }
+
+    #[test]
+    fn test_envelope_avoids_pops() {
+        for &waveform in &[Waveform::Square, Waveform::Triangle, Waveform::Sine, Waveform::Noise] {
+            let tone = Tone { frequency: 240.0, waveform, volume: 0.5 };
+            let mut synth = Synth::new(tone, 44_100);
+            let samples: Vec<f32> = (0..2000).map(|i| synth.next(i < 1000)).collect();
+
+            assert!(samples.iter().all(|s| s.abs() <= 0.5));
+            // The wave starts quietly.
+            assert!(samples[0].abs() < 0.01);
+
+            // Once released, it never gets louder than the envelope, which
+            // goes down by the same step on every sample until it is silent.
+            let step = 1.0 / (RELEASE * 44_100.0);
+            for (i, s) in samples[1000..].iter().enumerate() {
+                let envelope = (1.0 - (i + 1) as f32 * step).max(0.0);
+                assert!(s.abs() <= 0.5 * envelope + 1e-4, "{:?}: sample {} is {}", waveform, 1000 + i, s);
+            }
+            assert!(samples[1000 + (1.0 / step).ceil() as usize..].iter().all(|&s| s == 0.0));
+        }
+
+        // A square wave is always at the level of the envelope, so it
+        // decays steadily from the moment the buzzer stops.
+        let tone = Tone { frequency: 240.0, waveform: Waveform::Square, volume: 0.5 };
+        let mut synth = Synth::new(tone, 44_100);
+        let samples: Vec<f32> = (0..2000).map(|i| synth.next(i < 1000)).collect();
+        assert_eq!(0.5, samples[999].abs());
+        let levels: Vec<f32> = samples[999..].iter().map(|s| s.abs()).take_while(|&s| s > 0.0).collect();
+        assert!(levels.windows(2).all(|w| w[1] < w[0] && w[0] - w[1] < 0.002));
+
+        // Smooth waves never jump between neighbouring samples, whether
+        // the buzzer starts, plays or stops.
+        for &waveform in &[Waveform::Triangle, Waveform::Sine] {
+            let tone = Tone { frequency: 240.0, waveform, volume: 0.5 };
+            let mut synth = Synth::new(tone, 44_100);
+            let samples: Vec<f32> = (0..2000).map(|i| synth.next(i < 1000)).collect();
+            assert!(samples.windows(2).all(|w| (w[1] - w[0]).abs() < 0.02), "{:?}", waveform);
+        }
+    }
+
+    #[test]
+    fn test_playback_follows_frames() {
+        let timeline = Timeline::new();
+        let mut playback = Playback::new(44_100);
+
+        // Nothing plays until a few frames are ready.
+        timeline.push(false);
+        timeline.push(true);
+        assert!(!(0..2000).any(|_| playback.next(&timeline)));
+
+        for _ in 2..LATENCY + 4 {
+            timeline.push(false);
+        }
+
+        // A single frame of sound lasts exactly 1/60 s.
+        let samples: Vec<bool> = (0..735 * 4).map(|_| playback.next(&timeline)).collect();
+        assert!(samples[..735].iter().all(|&on| !on));
+        assert!(samples[735..735 * 2].iter().all(|&on| on));
+        assert!(samples[735 * 2..].iter().all(|&on| !on));
+    }
+}
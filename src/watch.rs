@@ -0,0 +1,97 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tells when a file has been modified, by checking its modification time
+/// every now and then.
+pub(crate) struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    next_check: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Self {
+        FileWatcher {
+            path: path.to_path_buf(),
+            modified: modified(path),
+            next_check: Instant::now() + POLL_INTERVAL,
+        }
+    }
+
+    /// Returns true if the file was modified since the last time it
+    /// returned true, or since the watcher was created. A file that
+    /// can't be read, e.g. while it is being replaced, doesn't count as
+    /// modified until it is back.
+    pub fn changed(&mut self, now: Instant) -> bool {
+        if now < self.next_check {
+            return false;
+        }
+        self.next_check = now + POLL_INTERVAL;
+
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, Instant, SystemTime};
+
+    use super::{FileWatcher, POLL_INTERVAL};
+
+    #[test]
+    fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("chippy-watch-{}.ch8", std::process::id()));
+        fs::write(&path, [0x12, 0x00]).unwrap();
+
+        let start = Instant::now();
+        let mut watcher = FileWatcher::new(&path);
+        assert!(!watcher.changed(start + POLL_INTERVAL));
+
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        // Not checked again until the interval is over.
+        assert!(!watcher.changed(start + POLL_INTERVAL));
+        assert!(watcher.changed(start + 2 * POLL_INTERVAL));
+        assert!(!watcher.changed(start + 3 * POLL_INTERVAL));
+
+        fs::remove_file(&path).unwrap();
+        assert!(!watcher.changed(start + 4 * POLL_INTERVAL));
+    }
+}
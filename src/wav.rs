@@ -0,0 +1,137 @@
+// MIT License
+//
+// Copyright (c) 2021 Pedro Rodrigues
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::sound::{Synth, Tone};
+
+pub(crate) const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / 60;
+const HEADER_SIZE: u32 = 44;
+
+/// Renders the buzzer into a 16-bit mono WAV file, one emulated frame
+/// at a time, so it doesn't depend on an audio device or on how fast
+/// the emulator runs.
+pub(crate) struct WavWriter<W: Write + Seek> {
+    writer: W,
+    synth: Synth,
+    samples: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, tone: Tone) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        WavWriter::new(BufWriter::new(file), tone)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W, tone: Tone) -> Result<Self, String> {
+        // The sizes are filled in by finish().
+        write_header(&mut writer, 0).map_err(|e| e.to_string())?;
+
+        Ok(WavWriter {
+            writer,
+            synth: Synth::new(tone, SAMPLE_RATE),
+            samples: 0,
+        })
+    }
+
+    /// Renders one frame, with the buzzer on if `on` is true.
+    pub fn frame(&mut self, on: bool) -> Result<(), String> {
+        let mut buffer = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = (self.synth.next(on) * i16::MAX as f32) as i16;
+            buffer.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        self.writer.write_all(&buffer).map_err(|e| e.to_string())?;
+        self.samples += SAMPLES_PER_FRAME;
+
+        Ok(())
+    }
+
+    /// Completes the header and flushes the file.
+    pub fn finish(mut self) -> Result<W, String> {
+        let result: std::io::Result<()> = (|| {
+            self.writer.seek(SeekFrom::Start(0))?;
+            write_header(&mut self.writer, self.samples * 2)?;
+            self.writer.seek(SeekFrom::End(0))?;
+            self.writer.flush()
+        })();
+
+        result.map_err(|e| e.to_string())?;
+        Ok(self.writer)
+    }
+}
+
+fn write_header<W: Write>(w: &mut W, data_size: u32) -> std::io::Result<()> {
+    w.write_all(b"RIFF")?;
+    w.write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    w.write_all(b"WAVEfmt ")?;
+    w.write_all(&16u32.to_le_bytes())?; // size of the fmt chunk
+    w.write_all(&1u16.to_le_bytes())?; // PCM
+    w.write_all(&1u16.to_le_bytes())?; // mono
+    w.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    w.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?; // bytes per second
+    w.write_all(&2u16.to_le_bytes())?; // bytes per sample
+    w.write_all(&16u16.to_le_bytes())?; // bits per sample
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{WavWriter, HEADER_SIZE, SAMPLES_PER_FRAME};
+    use crate::sound::Tone;
+
+    #[test]
+    fn test_wav_follows_frames() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), Tone::default()).unwrap();
+        for &on in &[false, true, false, false] {
+            wav.frame(on).unwrap();
+        }
+        let bytes = wav.finish().unwrap().into_inner();
+
+        let data_size = SAMPLES_PER_FRAME * 4 * 2;
+        assert_eq!((HEADER_SIZE + data_size) as usize, bytes.len());
+        assert_eq!(b"RIFF", &bytes[..4]);
+        assert_eq!(&(data_size + 36).to_le_bytes(), &bytes[4..8]);
+        assert_eq!(&data_size.to_le_bytes(), &bytes[40..44]);
+
+        let samples: Vec<i16> = bytes[44..]
+            .chunks(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        let frame = SAMPLES_PER_FRAME as usize;
+
+        assert!(samples[..frame].iter().all(|&s| s == 0));
+        assert!(samples[frame..2 * frame].iter().filter(|&&s| s != 0).count() > frame / 2);
+        // Only the release of the envelope spills over the next frame.
+        assert!(samples[3 * frame..].iter().all(|&s| s == 0));
+    }
+}